@@ -37,14 +37,15 @@ $('#undo_move').on('click', function () {
   }
 })
 
-function panic() {
-  alert("Unexpected failure. Click OK to reload the page.")
+function panic(reason) {
+  alert("Unexpected failure" + (reason ? " (" + reason + ")" : "") + ". Click OK to reload the page.")
   location.reload()
 }
 
 function check_panic_reply(reply) {
-  if(reply == 'illegal_input') {
-    panic()
+  // 'illegal_input: <reason>' when the FEN, a square or a move is rejected
+  if(reply.startsWith('illegal_input')) {
+    panic(reply.substring('illegal_input: '.length))
   }
 }
 
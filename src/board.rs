@@ -1,8 +1,12 @@
 use std::fmt;
 use std::str::FromStr;
 use std::cell::Cell;
+use std::sync::Arc;
 
-use crate::evaluation::{Value};
+use crate::evaluation::{Psqt, Value};
+use crate::params::{EvalParams, PARAMS_DEFAULT};
+use crate::nnue::Accumulator;
+use crate::zobrist::pawn_key;
 
 #[derive(Clone, Debug)]
 pub struct ParseError;
@@ -12,6 +16,55 @@ impl fmt::Display for ParseError {
 	}
 }
 
+/// Why a FEN string was rejected, by field
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FenError {
+	/// A required field is missing
+	Missing(&'static str),
+	/// Not 8 ranks in the piece placement
+	RankCount(usize),
+	/// A rank (1 to 8) doesn't describe 8 squares
+	RankLength(u8),
+	InvalidPiece(char),
+	/// No king, or more than one, of that color
+	KingCount(Color),
+	InvalidSideToMove(String),
+	InvalidCastling(String),
+	InvalidEnPassant(String),
+	/// A move counter, by name, is not a number
+	InvalidCounter(&'static str, String),
+	/// Something after the fullmove number
+	Trailing(String),
+	PawnOnBackRank(Pos),
+	/// More pieces than the pawns could have promoted to
+	TooManyPieces(Color),
+	/// Castling rights without the king and rook on their initial squares
+	CastlingRights(Color),
+	/// The side that just moved is in check
+	OpponentInCheck,
+}
+
+impl fmt::Display for FenError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			FenError::Missing(field) => write!(f, "missing {}", field),
+			FenError::RankCount(n) => write!(f, "piece placement: {} rank{} instead of 8", n, if *n == 1 { "" } else { "s" }),
+			FenError::RankLength(rank) => write!(f, "piece placement: rank {} doesn't have 8 squares", rank),
+			FenError::InvalidPiece(c) => write!(f, "piece placement: unknown piece '{}'", c),
+			FenError::KingCount(c) => write!(f, "piece placement: {:?} must have exactly one king", c),
+			FenError::InvalidSideToMove(s) => write!(f, "side to move: expected 'w' or 'b', found '{}'", s),
+			FenError::InvalidCastling(s) => write!(f, "castling rights: expected '-' or some of 'KQkq', found '{}'", s),
+			FenError::InvalidEnPassant(s) => write!(f, "en passant: '{}' is not the square a pawn just passed over with a double step", s),
+			FenError::InvalidCounter(name, s) => write!(f, "{}: '{}' is not a number", name, s),
+			FenError::Trailing(s) => write!(f, "unexpected '{}' after the fullmove number", s),
+			FenError::PawnOnBackRank(pos) => write!(f, "illegal position: pawn on {}", pos),
+			FenError::TooManyPieces(c) => write!(f, "illegal position: too many {:?} pieces for the pawns left", c),
+			FenError::CastlingRights(c) => write!(f, "illegal position: {:?} can't castle without its king and rook on their initial squares", c),
+			FenError::OpponentInCheck => write!(f, "illegal position: the side not to move is in check"),
+		}
+	}
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Color {
 	Black = 0,
@@ -225,109 +278,197 @@ pub struct Board {
 	pub can_castle_qs: [bool; 2],
 	pub can_castle_ks: [bool; 2],
 	pub stored_value: Cell<Option<Value>>,
+	pub stored_hash: Cell<Option<u64>>,
+	/// Zobrist hash of the pawns, updated incrementally
+	pub pawn_key: u64,
+	/// Material, piece-square and game phase terms, updated incrementally
+	pub psqt: Psqt,
+	/// Parameters of `psqt`, the built-in ones if not set
+	pub psqt_params: Option<Arc<EvalParams>>,
+	/// Neural network accumulators, only when evaluating with a network
+	pub nnue: Option<Accumulator>,
 }
 
 impl Board {
-	pub fn from_fen(s: &str) -> Result<Board, ParseError> {
-		fn parse_ranks(s: Option<&str>) -> Result <([[Option<Tile>; 8]; 8], [Pos; 2]), ParseError> {
-			let s = s.ok_or(ParseError)?;
-			let ranks = s.split('/');
+	/// Reads a position in Forsyth-Edwards Notation and checks that it is legal.
+	/// The en passant square and the move counters are optional, and checked but not kept
+	pub fn from_fen(s: &str) -> Result<Board, FenError> {
+		/// The tiles, and the king positions by color
+		type Placement = ([[Option<Tile>; 8]; 8], [Pos; 2]);
+		fn parse_ranks(s: Option<&str>) -> Result<Placement, FenError> {
+			let s = s.ok_or(FenError::Missing("piece placement"))?;
+			let ranks: Vec<&str> = s.split('/').collect();
+			if ranks.len() != 8 {
+				return Err(FenError::RankCount(ranks.len()))
+			}
 
 			let mut tiles:[[Option<Tile>; 8]; 8] = [[None; 8]; 8];
 			let mut king_pos:[Option<Pos>; 2] = [None; 2];
 
-			let mut rowcount:usize = 0;
-			for rank in ranks {
+			for (rowcount, rank) in ranks.iter().enumerate() {
+				let row = 7 - rowcount;
 				let mut colcount = 0; 
 
 				for x in rank.chars() {
 					if let Some(blanks) = char::to_digit(x, 10) {
 						colcount += blanks as usize
 					} else {
-						let t:Option<Tile> = Tile::from_char(x);
-						if t.is_none() {
-							return Err(ParseError)
+						let t = Tile::from_char(x).ok_or(FenError::InvalidPiece(x))?;
+						if colcount >= 8 {
+							return Err(FenError::RankLength(row as u8 + 1))
 						}
-						tiles[7-rowcount][colcount] = t;
-						if t.unwrap().piece == Piece::King {
-							king_pos[t.unwrap().color as usize] = Pos::at(colcount as i8,7-rowcount as i8);
+						tiles[row][colcount] = Some(t);
+						if t.piece == Piece::King {
+							if king_pos[t.color as usize].is_some() {
+								return Err(FenError::KingCount(t.color))
+							}
+							king_pos[t.color as usize] = Pos::at(colcount as i8, row as i8);
 						}
 						colcount += 1;
 					}
 				}
 				if colcount != 8 {
-					return Err(ParseError)
+					return Err(FenError::RankLength(row as u8 + 1))
 				}
-				rowcount += 1;
 			}
-			if rowcount != 8 {
-				return Err(ParseError)
+			match king_pos {
+				[Some(black), Some(white)] => Ok((tiles, [black, white])),
+				[None, _] => Err(FenError::KingCount(Color::Black)),
+				_ => Err(FenError::KingCount(Color::White)),
 			}
-			let king_pos = if let [Some(a), Some(b)] = king_pos {
-				[a,b]
-			} else {
-				return Err(ParseError)
-			};
-
-			Ok((tiles,king_pos))
 		}
 
-		fn parse_player(s: Option<&str>) -> Result <Color, ParseError> {
-			let s = s.ok_or(ParseError)?;
-
-			if s.len() != 1 {
-				return Err(ParseError)
-			}
-
-			if let Some(c) = s.chars().nth(0) {
-				if let Some(color) = Color::from_char(c) {
-					return Ok(color)
-				}
+		fn parse_player(s: Option<&str>) -> Result <Color, FenError> {
+			let s = s.ok_or(FenError::Missing("side to move"))?;
+			let mut chars = s.chars();
+			match (chars.next().and_then(Color::from_char), chars.next()) {
+				(Some(color), None) => Ok(color),
+				_ => Err(FenError::InvalidSideToMove(s.to_string())),
 			}
-			return Err(ParseError)
 		}
 
-		fn parse_castle(s: Option<&str>) -> Result <([bool; 2], [bool; 2]), ParseError> {
-			let s = s.ok_or(ParseError)?;
+		fn parse_castle(s: Option<&str>) -> Result <([bool; 2], [bool; 2]), FenError> {
+			let s = s.ok_or(FenError::Missing("castling rights"))?;
 
 			let mut can_castle_qs = [false ;2];
 			let mut can_castle_ks = [false ;2];
-
-			if s.find('K').is_some() {
-				can_castle_ks[Color::White as usize] = true;
-			}
-			if s.find('k').is_some() {
-				can_castle_ks[Color::Black as usize] = true;
-			}
-			if s.find('Q').is_some() {
-				can_castle_qs[Color::White as usize] = true;
+			if s == "-" {
+				return Ok((can_castle_qs, can_castle_ks));
 			}
-			if s.find('q').is_some() {
-				can_castle_qs[Color::Black as usize] = true;
+			for c in s.chars() {
+				let right = match c {
+					'K' => &mut can_castle_ks[Color::White as usize],
+					'k' => &mut can_castle_ks[Color::Black as usize],
+					'Q' => &mut can_castle_qs[Color::White as usize],
+					'q' => &mut can_castle_qs[Color::Black as usize],
+					_ => return Err(FenError::InvalidCastling(s.to_string())),
+				};
+				if *right {
+					return Err(FenError::InvalidCastling(s.to_string()));
+				}
+				*right = true;
 			}
 
 			Ok((can_castle_qs, can_castle_ks))
 		}
+
+		fn check_en_passant(s: Option<&str>, player: Color, tiles: &[[Option<Tile>; 8]; 8]) -> Result<(), FenError> {
+			match s {
+				None | Some("-") => Ok(()),
+				Some(sq) => {
+					// behind a pawn of the side that just moved, which moved two squares:
+					// the square it passed over and the one it came from are empty
+					let (row, forward) = if player == Color::White { (5, -1) } else { (2, 1) };
+					let pawn = Some(Tile { piece: Piece::Pawn, color: player.swap() });
+					let at = |row: i8, col: i8| tiles[row as usize][col as usize];
+					match Pos::from_coord(sq) {
+						Some(pos) if pos.row == row && at(row + forward, pos.col) == pawn
+							&& at(row, pos.col).is_none() && at(row - forward, pos.col).is_none() => Ok(()),
+						_ => Err(FenError::InvalidEnPassant(sq.to_string())),
+					}
+				},
+			}
+		}
+
+		fn check_counter(s: Option<&str>, name: &'static str) -> Result<(), FenError> {
+			match s.map(|n| n.parse::<u32>()) {
+				None | Some(Ok(_)) => Ok(()),
+				Some(Err(_)) => Err(FenError::InvalidCounter(name, s.unwrap_or_default().to_string())),
+			}
+		}
 		
 		let mut split = s.split_whitespace();
 		let (tiles, king_pos) = parse_ranks(split.next())?;
 		let player = parse_player(split.next())?;
 		let (can_castle_qs, can_castle_ks) = parse_castle(split.next())?;
+		check_en_passant(split.next(), player, &tiles)?;
+		check_counter(split.next(), "halfmove clock")?;
+		check_counter(split.next(), "fullmove number")?;
+		if let Some(extra) = split.next() {
+			return Err(FenError::Trailing(extra.to_string()));
+		}
 
-		// TODO, parse the en-passant and move counts
-
-		let b = Board {
+		let mut b = Board {
 			tiles,
 			player,
 			king_pos,
 			can_castle_qs,
 			can_castle_ks,
 			stored_value: Cell::default(),
+			stored_hash: Cell::default(),
+			pawn_key: 0,
+			psqt: Psqt::default(),
+			psqt_params: None,
+			nnue: None,
 		};
+		b.validate()?;
+		b.psqt = Psqt::scan(&b, &PARAMS_DEFAULT);
+		b.pawn_key = b.scan_pawn_hash();
 
 		Ok(b)
 	}
 
+	/// Checks that the position can arise in a game: no pawns on the first or last rank,
+	/// no more pieces than promotions allow, castling rights matching the king and rook
+	/// squares, and the side that just moved not left in check
+	pub fn validate(&self) -> Result<(), FenError> {
+		for color in [Color::Black, Color::White] {
+			let mut count = [0; 6];
+			for (row, tiles) in self.tiles.iter().enumerate() {
+				for (col, tile) in tiles.iter().enumerate() {
+					match tile {
+						Some(t) if t.color == color => {
+							if t.piece == Piece::Pawn && (row == 0 || row == 7) {
+								return Err(FenError::PawnOnBackRank(Pos::at(col as i8, row as i8).unwrap()));
+							}
+							count[t.piece as usize] += 1;
+						},
+						_ => (),
+					}
+				}
+			}
+			// each piece beyond the initial ones is a promoted pawn
+			let promoted: usize = [(Piece::Knight, 2), (Piece::Bishop, 2), (Piece::Rook, 2), (Piece::Queen, 1)].iter()
+				.map(|&(p, n)| count[p as usize].max(n) - n)
+				.sum();
+			if count[Piece::Pawn as usize] + promoted > 8 {
+				return Err(FenError::TooManyPieces(color));
+			}
+
+			let row = if color == Color::White { 0 } else { 7 };
+			let rook_at = |col| *self.at(Pos::at(col, row).unwrap()) == Some(Tile { piece: Piece::Rook, color });
+			let king_home = self.king_pos[color as usize] == Pos::at(4, row).unwrap();
+			if (self.can_castle_ks[color as usize] && !(king_home && rook_at(7)))
+					|| (self.can_castle_qs[color as usize] && !(king_home && rook_at(0))) {
+				return Err(FenError::CastlingRights(color));
+			}
+		}
+		if self.is_king_in_check(self.player.swap()) {
+			return Err(FenError::OpponentInCheck);
+		}
+		Ok(())
+	}
+
 	pub fn to_fen(&self) -> String {
 		let mut s = String::new();
 
@@ -399,6 +540,9 @@ impl Board {
 	pub fn make_move(&mut self, mv: &Move) -> () {
 		// The source tile before the move
 		let f = self.at(mv.f_pos).unwrap();
+		// The captured piece, if any
+		let captured = *self.at(mv.t_pos);
+		let mut rook_move = None;
 
 		if f.piece == Piece::Pawn 
 				&& ((f.color == Color::Black && mv.t_pos.row == 0)
@@ -422,10 +566,12 @@ impl Board {
 						debug_assert!(self.can_castle_ks[t.color as usize]);
 						self.tiles[7][5] = self.tiles[7][7];
 						self.tiles[7][7] = None;
+						rook_move = Some((Pos::at(7,7).unwrap(), Pos::at(5,7).unwrap()));
 					} else if mv.t_pos == Pos::at(2,7).unwrap() {
 						debug_assert!(self.can_castle_qs[t.color as usize]);
 						self.tiles[7][3] = self.tiles[7][0];
 						self.tiles[7][0] = None;
+						rook_move = Some((Pos::at(0,7).unwrap(), Pos::at(3,7).unwrap()));
 					}
 				},
 				Color::White => {
@@ -433,14 +579,36 @@ impl Board {
 						debug_assert!(self.can_castle_ks[t.color as usize]);
 						self.tiles[0][5] = self.tiles[0][7];
 						self.tiles[0][7] = None;
+						rook_move = Some((Pos::at(7,0).unwrap(), Pos::at(5,0).unwrap()));
 					} else if mv.t_pos == Pos::at(2,0).unwrap() {
 						debug_assert!(self.can_castle_qs[t.color as usize]);
 						self.tiles[0][3] = self.tiles[0][0];
 						self.tiles[0][0] = None;
+						rook_move = Some((Pos::at(0,0).unwrap(), Pos::at(3,0).unwrap()));
 					}
 				}
 			}
 		}
+
+		/* Keep the incremental evaluation terms, the pawn hash and the network accumulators in sync */
+		let rook = Tile { piece: Piece::Rook, color: t.color };
+		let removed = [Some((f, mv.f_pos)), captured.map(|c| (c, mv.t_pos)), rook_move.map(|(from, _)| (rook, from))];
+		let added = [Some((t, mv.t_pos)), rook_move.map(|(_, to)| (rook, to))];
+		let params = self.psqt_params.as_deref().unwrap_or(&PARAMS_DEFAULT);
+		for &(tile, pos) in removed.iter().flatten() {
+			self.psqt.remove(params, tile, pos);
+			self.pawn_key ^= pawn_key(tile, pos);
+			if let Some(acc) = self.nnue.as_mut() {
+				acc.remove(tile, pos);
+			}
+		}
+		for &(tile, pos) in added.iter().flatten() {
+			self.psqt.add(params, tile, pos);
+			self.pawn_key ^= pawn_key(tile, pos);
+			if let Some(acc) = self.nnue.as_mut() {
+				acc.add(tile, pos);
+			}
+		}
 	}
 
 	/// Clone current board and apply given move.
@@ -509,9 +677,21 @@ impl Board {
 		}
 
 		b.stored_value = Cell::default();
+		b.stored_hash = Cell::default();
 
 		b
 	}
+
+	/// Clone current board and pass the turn to the opponent without moving.
+	/// Used by null-move pruning
+	pub fn clone_null_move(&self) -> Board {
+		let mut b = self.clone();
+		b.player = b.player.swap();
+		// evaluations may depend on the side to move
+		b.stored_value = Cell::default();
+		b.stored_hash = Cell::default();
+		b
+	}
 	
 	#[allow(dead_code)]
 	pub fn as_ascii(&self) -> String {
@@ -548,7 +728,7 @@ pub enum GameEnd {
 
 #[cfg(test)]
 mod tests {
-	use crate::board::{Pos,Move,Board,Color};
+	use crate::board::{Pos,Move,Board,Color,FenError};
 	#[test]
 	pub fn test_parse_pos() {
 		let coords_in = "G8";
@@ -573,4 +753,28 @@ mod tests {
 		debug_assert_eq!(b.king_pos[Color::White as usize], Pos::at(4,0).unwrap());
 		debug_assert_eq!(b.king_pos[Color::Black as usize], Pos::at(4,7).unwrap());
 	}
+
+	#[test]
+	pub fn test_fen_errors() {
+		let err = |fen: &str| Board::from_fen(fen).err();
+		debug_assert_eq!(err("4k3/8/8/8/8/8/8/4K3"), Some(FenError::Missing("side to move")));
+		debug_assert_eq!(err("4k3/8/8/8/8/8/8/4X1K1 w - - 0 1"), Some(FenError::InvalidPiece('X')));
+		debug_assert_eq!(err("4k3/8/8/8/8/8/8/4K3 x - - 0 1"), Some(FenError::InvalidSideToMove("x".to_string())));
+		debug_assert_eq!(err("4k3/8/8/8/8/8/8/4K3 w - - 0 1 x"), Some(FenError::Trailing("x".to_string())));
+		debug_assert_eq!(err("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNP w Qkq - 0 1"),
+			Some(FenError::PawnOnBackRank(Pos::at(7,0).unwrap())));
+		debug_assert_eq!(err("4k3/8/8/8/8/8/PPPPPPPP/QQ2K3 w - - 0 1"), Some(FenError::TooManyPieces(Color::White)));
+		debug_assert_eq!(err("4k3/8/8/8/8/8/8/4K3 w KQkq - 0 1"), Some(FenError::CastlingRights(Color::Black)));
+		debug_assert_eq!(err("4k3/8/8/8/8/8/8/4R1K1 w - - 0 1"), Some(FenError::OpponentInCheck));
+		debug_assert!(err("4k3/8/8/8/8/8/8/4R1K1 b - - 0 1").is_none());
+		debug_assert_eq!(err("4k3/8/8/8/4K3 w - - 0 1").unwrap().to_string(), "piece placement: 5 ranks instead of 8");
+		debug_assert_eq!(err("8 w - - 0 1").unwrap().to_string(), "piece placement: 1 rank instead of 8");
+		// en passant: behind a pawn that just made a double step
+		debug_assert!(err("4k3/8/8/4p3/8/8/8/4K3 w - e6 0 1").is_none());
+		debug_assert!(err("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").is_none());
+		debug_assert_eq!(err("4k3/8/8/8/8/8/8/4K3 w - e6 0 1"), Some(FenError::InvalidEnPassant("e6".to_string())));
+		debug_assert_eq!(err("4k3/8/4n3/4p3/8/8/8/4K3 w - e6 0 1"), Some(FenError::InvalidEnPassant("e6".to_string())));
+		debug_assert_eq!(err("4k3/4n3/8/4p3/8/8/8/4K3 w - e6 0 1"), Some(FenError::InvalidEnPassant("e6".to_string())));
+		debug_assert_eq!(err("4k3/8/8/4p3/8/8/8/4K3 w - e3 0 1"), Some(FenError::InvalidEnPassant("e3".to_string())));
+	}
 }
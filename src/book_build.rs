@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use crate::board::{Board, Color, Move, Piece};
+use crate::book::{Book, Entry, ENTRY_SIZE};
+use crate::pgn::{PgnError, PgnGame};
+
+/*
+ * Building Polyglot books from PGN games, for the `book build` subcommand.
+ * Only the engine binary has it: the Wasm library reads books but doesn't write them.
+*/
+
+/// Which moves of the games make it into a book built by `Book::build`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BuildLimits {
+	/// Only the first plies of each game are recorded
+	pub max_ply: usize,
+	/// Moves played in fewer games are left out
+	pub min_games: u32,
+	/// Moves with a lower average score for the side playing them (0 to 1) are left out
+	pub min_score: f64,
+}
+
+/// How a move did in the games it was played in
+#[derive(Debug, Default, Clone, Copy)]
+struct MoveStats {
+	games: u32,
+	/// 2 per win, 1 per draw
+	points: u32,
+}
+
+impl Book {
+	/// Writes the book in Polyglot format, with the moves of each position by decreasing weight
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut entries = self.entries.clone();
+		entries.sort_by(|a, b| a.key.cmp(&b.key).then(b.weight.cmp(&a.weight)));
+		let mut bytes = Vec::with_capacity(entries.len() * ENTRY_SIZE);
+		for e in entries {
+			bytes.extend(e.key.to_be_bytes());
+			bytes.extend(e.mv.to_be_bytes());
+			bytes.extend(e.weight.to_be_bytes());
+			bytes.extend([0; 4]);
+		}
+		bytes
+	}
+
+	/// Number of (position, move) entries
+	pub fn entry_count(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Builds a book from the moves played in the games, weighted by their results
+	/// as Polyglot does: 2 per win and 1 per draw.
+	/// Games without a result are left out, as are games that don't replay within
+	/// the plies recorded, which are reported in the returned errors.
+	pub fn build(games: &[PgnGame], limits: &BuildLimits) -> (Book, Vec<PgnError>) {
+		let mut stats: HashMap<(u64, u16), MoveStats> = HashMap::new();
+		let mut errors = Vec::new();
+		for (n, game) in games.iter().enumerate() {
+			let white_score = match game.white_score() {
+				Some(s) => s,
+				None => continue,
+			};
+			let mut opening = game.clone();
+			opening.moves.truncate(limits.max_ply);
+			let record = match opening.replay(n + 1) {
+				Ok(record) => record,
+				Err(e) => {
+					errors.push(e);
+					continue;
+				}
+			};
+			let mut b = match Board::from_fen(&record.start_fen) {
+				Ok(b) => b,
+				Err(_) => continue,
+			};
+			for m in &record.moves {
+				let score = if b.player == Color::White { white_score } else { 1.0 - white_score };
+				let s = stats.entry((b.polyglot_key(), encode_move(&b, m.mv))).or_default();
+				s.games += 1;
+				s.points += (2.0 * score) as u32;
+				b = b.clone_apply_move(&m.mv);
+			}
+		}
+
+		let mut entries: Vec<Entry> = stats.into_iter()
+			.filter(|(_, s)| s.games >= limits.min_games && s.points as f64 / (2 * s.games) as f64 >= limits.min_score)
+			// moves that never scored stay playable, with the lowest weight
+			.map(|((key, mv), s)| Entry { key, mv, weight: s.points.clamp(1, u16::MAX as u32) as u16 })
+			.collect();
+		entries.sort_by(|a, b| a.key.cmp(&b.key).then(b.weight.cmp(&a.weight)).then(a.mv.cmp(&b.mv)));
+		(Book { entries }, errors)
+	}
+}
+
+/// Converts a move of the board to a Polyglot move, the inverse of `book::decode_move`
+fn encode_move(b: &Board, mv: Move) -> u16 {
+	let mut t_pos = mv.t_pos;
+	let mut promotion = 0;
+	if let Some(f) = b.at(mv.f_pos) {
+		if f.piece == Piece::King && (mv.t_pos.col - mv.f_pos.col).abs() == 2 {
+			t_pos.col = if mv.t_pos.col > mv.f_pos.col { 7 } else { 0 };
+		}
+		if f.piece == Piece::Pawn && (mv.t_pos.row == 0 || mv.t_pos.row == 7) {
+			promotion = 4;
+		}
+	}
+	(promotion << 12) | ((mv.f_pos.row as u16) << 9) | ((mv.f_pos.col as u16) << 6) | ((t_pos.row as u16) << 3) | t_pos.col as u16
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::board::{Board,Color,Move};
+	use crate::book::Book;
+	use crate::book_build::BuildLimits;
+	use crate::pgn::read_games;
+
+	#[test]
+	pub fn test_build() {
+		let games = read_games("1. e4 e5 2. Nf3 1-0\n1. e4 c5 0-1\n1. e4 e5 1/2-1/2\n1. d4 d5 *\n1. Nf3 Nf6 2. Ke3 1-0\n");
+		let limits = BuildLimits { max_ply: 2, min_games: 1, min_score: 0.0 };
+		let (book, errors) = Book::build(&games, &limits);
+		debug_assert!(errors.is_empty());
+		let start = Board::new(Color::White);
+		// 1 win, 1 draw and 1 loss for e4, 1 win for Nf3; d4 has no result
+		debug_assert_eq!(book.moves(&start), vec![("e2e4".parse().unwrap(), 3), ("g1f3".parse().unwrap(), 2)]);
+		let e4 = start.clone_apply_move(&"e2e4".parse::<Move>().unwrap());
+		debug_assert_eq!(book.moves(&e4), vec![("c7c5".parse().unwrap(), 2), ("e7e5".parse().unwrap(), 1)]);
+		debug_assert_eq!(Book::from_bytes(&book.to_bytes()), Ok(book));
+
+		let limits = BuildLimits { max_ply: 10, min_games: 2, min_score: 0.5 };
+		let (book, errors) = Book::build(&games, &limits);
+		debug_assert_eq!(errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(), vec!["game 5, ply 3: Ke3: illegal move"]);
+		debug_assert_eq!(book.entry_count(), 1);
+		// errors past the plies recorded don't matter
+		let (_, errors) = Book::build(&games, &BuildLimits { max_ply: 2, ..limits });
+		debug_assert!(errors.is_empty());
+	}
+}
@@ -0,0 +1,179 @@
+use crate::board::{Board, Color, Piece};
+use crate::evaluation::{Value, EG};
+use crate::kpk;
+
+/*
+ * Specialised evaluation of endgames the general evaluation doesn't know how to win:
+ * https://www.chessprogramming.org/Mop-up_Evaluation
+ * The winning side drives the defending king to the edge (or to the right corner with
+ * bishop and knight) and brings its own king closer. KPK is looked up in the bitbase.
+*/
+
+/// Above any regular evaluation, below the tablebase and mate scores
+pub const KNOWN_WIN: Value = 10000;
+
+/* Squares are numbered `row * 8 + col` */
+#[inline]
+fn distance(a: usize, b: usize) -> Value {
+	let dr = ((a >> 3) as Value - (b >> 3) as Value).abs();
+	let df = ((a & 7) as Value - (b & 7) as Value).abs();
+	dr.max(df)
+}
+
+/// Larger near the edges
+#[inline]
+fn push_to_edge(sq: usize) -> Value {
+	let rd = (sq >> 3).min(7 - (sq >> 3)) as Value;
+	let fd = (sq & 7).min(7 - (sq & 7)) as Value;
+	90 - (7 * fd * fd / 2 + 7 * rd * rd / 2)
+}
+
+/// Larger near the a1 and h8 corners
+#[inline]
+fn push_to_dark_corner(sq: usize) -> Value {
+	(7 - (sq >> 3) as Value - (sq & 7) as Value).abs()
+}
+
+/// Larger as the kings get closer
+#[inline]
+fn push_close(a: usize, b: usize) -> Value {
+	140 - 20 * distance(a, b)
+}
+
+/// Pieces of each color, by piece type, with a square of each: the endgames known have
+/// at most one piece of each type but kings
+struct Material {
+	count: [[u8; 6]; 2],
+	square: [[usize; 6]; 2],
+}
+
+impl Material {
+	fn of(b: &Board) -> Self {
+		let mut m = Material { count: [[0; 6]; 2], square: [[0; 6]; 2] };
+		for (row, tiles) in b.tiles.iter().enumerate() {
+			for (col, tile) in tiles.iter().enumerate() {
+				if let Some(t) = tile {
+					m.count[t.color as usize][t.piece as usize] += 1;
+					m.square[t.color as usize][t.piece as usize] = row * 8 + col;
+				}
+			}
+		}
+		m
+	}
+
+	#[inline]
+	fn king(&self, c: Color) -> usize {
+		self.square[c as usize][Piece::King as usize]
+	}
+
+	/// Non-king material, in endgame values
+	fn value(&self, c: Color) -> Value {
+		(0..5).map(|p| self.count[c as usize][p] as Value * EG[p]).sum()
+	}
+}
+
+impl Board {
+	/// Score of the known endgames from White's point of view, `None` for the others
+	pub fn endgame_value(&self) -> Option<Value> {
+		// a queen and a rook at most on top of pawns and the minor pieces below
+		if self.psqt.phase > 8 {
+			return None;
+		}
+		let m = Material::of(self);
+		for &strong in &[Color::White, Color::Black] {
+			let weak = strong.swap();
+			let s = &m.count[strong as usize];
+			let bare_king = m.count[weak as usize] == [0, 0, 0, 0, 0, 1];
+			let v = if bare_king && s[Piece::Queen as usize] + s[Piece::Rook as usize] > 0 {
+				self.kxk(&m, strong)
+			} else if bare_king && *s == [0, 1, 1, 0, 0, 1] {
+				kbnk(&m, strong)
+			} else if bare_king && *s == [1, 0, 0, 0, 0, 1] {
+				self.kpk(&m, strong)
+			} else if *s == [0, 0, 0, 0, 1, 1] && m.count[weak as usize] == [1, 0, 0, 0, 0, 1] {
+				kqkp(&m, strong)
+			} else {
+				continue;
+			};
+			return Some(if strong == Color::White { v } else { -v });
+		}
+		None
+	}
+
+	/// Enough material to mate against a bare king: push it to the edge
+	fn kxk(&self, m: &Material, strong: Color) -> Value {
+		let weak = strong.swap();
+		if self.player == weak && self.generate_all().is_empty() {
+			// stalemate
+			return 0;
+		}
+		KNOWN_WIN + m.value(strong) + push_to_edge(m.king(weak)) + push_close(m.king(strong), m.king(weak))
+	}
+
+	/// Pawn versus bare king: won or drawn according to the bitbase
+	fn kpk(&self, m: &Material, strong: Color) -> Value {
+		let pawn = m.square[strong as usize][Piece::Pawn as usize];
+		if !kpk::probe(m.king(strong), pawn, m.king(strong.swap()), strong, self.player) {
+			return 0;
+		}
+		// closer to promotion is better
+		let rank = if strong == Color::White { pawn >> 3 } else { 7 - (pawn >> 3) };
+		KNOWN_WIN + EG[Piece::Pawn as usize] + rank as Value
+	}
+}
+
+/// Bishop and knight: mate only happens in a corner of the bishop's color
+fn kbnk(m: &Material, strong: Color) -> Value {
+	let weak_king = m.king(strong.swap());
+	let bishop = m.square[strong as usize][Piece::Bishop as usize];
+	// a1 is dark: with a light-squared bishop, mirror to aim at a8 and h1
+	let light_bishop = ((bishop >> 3) + (bishop & 7)) % 2 == 1;
+	let target = if light_bishop { weak_king ^ 7 } else { weak_king };
+	KNOWN_WIN + push_close(m.king(strong), weak_king) + 420 * push_to_dark_corner(target)
+}
+
+/// Queen versus pawn: a win, unless the pawn is a rook or bishop pawn on the seventh
+/// rank supported by its king, which draws by stalemate tricks
+fn kqkp(m: &Material, strong: Color) -> Value {
+	let weak = strong.swap();
+	let weak_king = m.king(weak);
+	let pawn = m.square[weak as usize][Piece::Pawn as usize];
+	let mut v = push_close(m.king(strong), weak_king);
+	let seventh = if weak == Color::White { 6 } else { 1 };
+	if pawn >> 3 != seventh || distance(weak_king, pawn) != 1 || ![0, 2, 5, 7].contains(&(pawn & 7)) {
+		v += EG[Piece::Queen as usize] - EG[Piece::Pawn as usize];
+	}
+	v
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::board::Board;
+	use crate::endgame::KNOWN_WIN;
+
+	fn value(fen: &str) -> Option<i16> {
+		Board::from_fen(fen).unwrap().endgame_value()
+	}
+
+	#[test]
+	pub fn test_endgames() {
+		// the defending king belongs on the edge
+		let edge = value("k7/8/2K5/8/8/8/7Q/8 w - - 0 1").unwrap();
+		let center = value("8/8/2K5/8/4k3/8/7Q/8 w - - 0 1").unwrap();
+		debug_assert!(edge > center && center > KNOWN_WIN);
+		debug_assert!(value("8/8/8/8/8/2k5/7r/K7 w - - 0 1").unwrap() < -KNOWN_WIN);
+		// stalemate
+		debug_assert_eq!(value("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), Some(0));
+		// dark-squared bishop: a1 or h8
+		let right = value("8/8/8/8/8/4B3/1N6/k1K5 w - - 0 1").unwrap();
+		let wrong = value("k7/8/8/8/8/4B3/1N6/2K5 w - - 0 1").unwrap();
+		debug_assert!(right > wrong);
+		debug_assert!(value("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").unwrap() > KNOWN_WIN);
+		debug_assert_eq!(value("4k3/8/8/8/8/8/P7/K7 b - - 0 1"), Some(0));
+		// queen against a bishop pawn about to promote with its king by
+		let drawish = value("8/8/8/8/8/1K6/2pk4/Q7 w - - 0 1").unwrap();
+		let winning = value("8/8/8/8/8/1K6/3pk3/Q7 w - - 0 1").unwrap();
+		debug_assert!(drawish < 200 && winning > 800);
+		debug_assert_eq!(value("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), None);
+	}
+}
@@ -1,14 +1,56 @@
+use std::fmt;
+use std::sync::Arc;
+
 use crate::board::{Board, Color, Piece, Pos, Tile};
+use crate::misc::{Evaluation, Options};
+use crate::nnue::Nnue;
+use crate::params::{EvalParams, PARAMS_DEFAULT, Weight};
+use crate::trace::EvalTrace;
 
 pub type Value = i16;
 
+/* Score of being checkmated at the root. Mates further away score closer to zero,
+ * so that the search prefers the shortest mate (and the longest defence) */
+pub const MATE: Value = 30000;
+/* Upper bound on the length of a search line, in plies */
+pub const MAX_PLY: Value = 128;
+
+/// Score for the side to move when it is checkmated `ply` plies away from the root
+#[inline]
+pub fn mated_in(ply: u8) -> Value {
+	-MATE + ply as Value
+}
+
+/// Score for the side to move when it gives checkmate `ply` plies away from the root
+#[inline]
+pub fn mate_in(ply: u8) -> Value {
+	MATE - ply as Value
+}
+
+#[inline]
+pub fn is_mate_score(v: Value) -> bool {
+	v.abs() >= MATE - MAX_PLY
+}
+
+/// Converts a mate score into a number of moves (not plies) to mate,
+/// negative when the side to move is getting mated
+pub fn mate_distance(v: Value) -> Option<i8> {
+	if !is_mate_score(v) {
+		None
+	} else if v > 0 {
+		Some(((MATE - v + 1) / 2) as i8)
+	} else {
+		Some((-(MATE + v) / 2) as i8)
+	}
+}
+
 /* 
  * Simplified Evaluation Function: https://www.chessprogramming.org/Simplified_Evaluation_Function
  * Please note that the values presented here have been designed specifically to compensate for the lack of any other chess knowledge, and NOT for being supplemented by it.
 */
 
 /* Value of each piece */
-const MG: [Value; 6] = [ 100, 320, 330, 500, 900, 20000]; 
+pub const MG: [Value; 6] = [ 100, 320, 330, 500, 900, 20000]; 
 
 /* Pawn positional bonus */
 const MG_POS_BONUS_PAWN: [[Value; 8]; 8] = [
@@ -83,7 +125,7 @@ const MG_POS_BONUS_KING: [[Value; 8]; 8] = [
 ];
 
 /* Positional bonus per piece */
-const MG_POS_BONUS: [[[Value; 8]; 8]; 6] = [
+pub const MG_POS_BONUS: [[[Value; 8]; 8]; 6] = [
 	MG_POS_BONUS_PAWN,
 	MG_POS_BONUS_KNIGHT,
 	MG_POS_BONUS_BISHOP,
@@ -92,6 +134,168 @@ const MG_POS_BONUS: [[[Value; 8]; 8]; 6] = [
 	MG_POS_BONUS_KING,
 ];
 
+/* 
+ * Endgame counterparts of the tables above. The evaluation interpolates between
+ * middlegame and endgame scores according to the material left on the board
+ * (tapered evaluation: https://www.chessprogramming.org/Tapered_Eval)
+*/
+
+/* Value of each piece in the endgame. Pawns gain value as they get closer to promotion */
+pub const EG: [Value; 6] = [ 120, 300, 330, 520, 900, 20000];
+
+/* Pawn positional bonus: in the endgame, advancing is all that matters */
+const EG_POS_BONUS_PAWN: [[Value; 8]; 8] = [
+	[  0,   0,   0,   0,   0,   0,   0,   0],
+	[  0,   0,   0,   0,   0,   0,   0,   0],
+	[ 10,  10,  10,  10,  10,  10,  10,  10],
+	[ 20,  20,  20,  20,  20,  20,  20,  20],
+	[ 35,  35,  35,  35,  35,  35,  35,  35],
+	[ 60,  60,  60,  60,  60,  60,  60,  60],
+	[100, 100, 100, 100, 100, 100, 100, 100],
+	[  0,   0,   0,   0,   0,   0,   0,   0],
+];
+
+/* Knight positional bonus: centralization matters in every phase */
+const EG_POS_BONUS_KNIGHT: [[Value; 8]; 8] = MG_POS_BONUS_KNIGHT;
+
+/* Bishop positional bonus */
+const EG_POS_BONUS_BISHOP: [[Value; 8]; 8] = [
+	[-20, -10, -10, -10, -10, -10, -10, -20],
+	[-10,   0,   0,   0,   0,   0,   0, -10],
+	[-10,   0,   5,  10,  10,   5,   0, -10],
+	[-10,   5,  10,  10,  10,  10,   5, -10],
+	[-10,   5,  10,  10,  10,  10,   5, -10],
+	[-10,   0,   5,  10,  10,   5,   0, -10],
+	[-10,   0,   0,   0,   0,   0,   0, -10],
+	[-20, -10, -10, -10, -10, -10, -10, -20],
+];
+
+/* Rook positional bonus: no need to stay home to castle anymore */
+const EG_POS_BONUS_ROOK: [[Value; 8]; 8] = [
+	[  0,   0,   0,   0,   0,   0,   0,   0],
+	[  0,   0,   0,   0,   0,   0,   0,   0],
+	[  0,   0,   0,   0,   0,   0,   0,   0],
+	[  0,   0,   0,   0,   0,   0,   0,   0],
+	[  0,   0,   0,   0,   0,   0,   0,   0],
+	[  0,   0,   0,   0,   0,   0,   0,   0],
+	[ 10,  10,  10,  10,  10,  10,  10,  10],
+	[  0,   0,   0,   0,   0,   0,   0,   0],
+];
+
+/* Queen positional bonus */
+const EG_POS_BONUS_QUEEN: [[Value; 8]; 8] = [
+	[-20, -10, -10,  -5,  -5, -10, -10, -20],
+	[-10,   0,   0,   0,   0,   0,   0, -10],
+	[-10,   0,   5,   5,   5,   5,   0, -10],
+	[ -5,   0,   5,  10,  10,   5,   0,  -5],
+	[ -5,   0,   5,  10,  10,   5,   0,  -5],
+	[-10,   0,   5,   5,   5,   5,   0, -10],
+	[-10,   0,   0,   0,   0,   0,   0, -10],
+	[-20, -10, -10,  -5,  -5, -10, -10, -20],
+];
+
+/* King positional bonus: the king must come out and fight */
+const EG_POS_BONUS_KING: [[Value; 8]; 8] = [
+	[-50, -30, -30, -30, -30, -30, -30, -50],
+	[-30, -30,   0,   0,   0,   0, -30, -30],
+	[-30, -10,  20,  30,  30,  20, -10, -30],
+	[-30, -10,  30,  40,  40,  30, -10, -30],
+	[-30, -10,  30,  40,  40,  30, -10, -30],
+	[-30, -10,  20,  30,  30,  20, -10, -30],
+	[-30, -20, -10,   0,   0, -10, -20, -30],
+	[-50, -40, -30, -20, -20, -30, -40, -50],
+];
+
+/* Positional bonus per piece */
+pub const EG_POS_BONUS: [[[Value; 8]; 8]; 6] = [
+	EG_POS_BONUS_PAWN,
+	EG_POS_BONUS_KNIGHT,
+	EG_POS_BONUS_BISHOP,
+	EG_POS_BONUS_ROOK,
+	EG_POS_BONUS_QUEEN,
+	EG_POS_BONUS_KING,
+];
+
+/* Contribution of each piece to the game phase */
+const PHASE_WEIGHT: [i32; 6] = [0, 1, 1, 2, 4, 0];
+/* Game phase with all pieces on the board */
+pub const MAX_PHASE: i32 = 24;
+
+impl Board {
+	/// Game phase from the remaining material:
+	/// `MAX_PHASE` in the opening, down to 0 in a pawn (or bare kings) ending
+	pub fn game_phase(&self) -> i32 {
+		debug_assert_eq!(self.psqt.phase, self.tiles.iter().flatten()
+			.filter_map(|t| t.map(|tile| PHASE_WEIGHT[tile.piece as usize]))
+			.sum::<i32>());
+		self.psqt.phase.min(MAX_PHASE)
+	}
+}
+
+/// Material, piece-square and game phase terms, kept up to date by `make_move`
+/// as pieces come and go, so that they don't need a scan of the board.
+/// They are computed with the parameters of `Board::psqt_params`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Psqt {
+	/// Without the kings, as in `Board::material_pst`
+	pub material: [Weight; 2],
+	pub pst: [Weight; 2],
+	/// Not capped: promotions can take it above `MAX_PHASE`
+	pub phase: i32,
+}
+
+impl Psqt {
+	/// Computes the terms of the board from scratch
+	pub fn scan(b: &Board, params: &EvalParams) -> Self {
+		let mut psqt = Psqt::default();
+		for c in 0..8 {
+			for r in 0..8 {
+				let pos = Pos::at(c, r).unwrap();
+				if let Some(tile) = b.at(pos) {
+					psqt.update(params, *tile, pos, 1);
+				}
+			}
+		}
+		psqt
+	}
+
+	fn update(&mut self, params: &EvalParams, tile: Tile, pos: Pos, sign: i32) {
+		let p = tile.piece as usize;
+		let sq = match tile.color {
+			Color::Black => (7 - pos.row as usize) * 8 + 7 - pos.col as usize,
+			Color::White => pos.row as usize * 8 + pos.col as usize,
+		};
+		let color = tile.color as usize;
+		// both kings are always there, their value would only add noise to the traces
+		if tile.piece != Piece::King {
+			self.material[color].0 += sign * params.piece_value[p].0;
+			self.material[color].1 += sign * params.piece_value[p].1;
+		}
+		self.pst[color].0 += sign * params.pst[p][sq].0;
+		self.pst[color].1 += sign * params.pst[p][sq].1;
+		self.phase += sign * PHASE_WEIGHT[p];
+	}
+
+	/// A piece was put on the board
+	#[inline]
+	pub fn add(&mut self, params: &EvalParams, tile: Tile, pos: Pos) {
+		self.update(params, tile, pos, 1);
+	}
+
+	/// A piece was taken off the board
+	#[inline]
+	pub fn remove(&mut self, params: &EvalParams, tile: Tile, pos: Pos) {
+		self.update(params, tile, pos, -1);
+	}
+}
+
+/// Interpolates between a middlegame and an endgame score
+#[inline]
+pub fn taper(mg: i32, eg: i32, phase: i32) -> Value {
+	((mg * phase + eg * (MAX_PHASE - phase)) / MAX_PHASE) as Value
+}
+
+#[allow(dead_code)]
 pub trait Valuable {
 	fn value(&self) -> Value;
 }
@@ -111,30 +315,192 @@ impl Valuable for Tile {
 }
 
 
-impl Valuable for Board {
-	fn value(&self) -> Value {
+impl Board {
+	/// Parameters the incremental terms are computed with
+	#[inline]
+	pub fn psqt_params(&self) -> &EvalParams {
+		self.psqt_params.as_deref().unwrap_or(&PARAMS_DEFAULT)
+	}
+
+	/// Computes the incremental terms again with other parameters, which `make_move`
+	/// then keeps up to date. The built-in parameters if not set
+	pub fn set_psqt_params(&mut self, params: Option<Arc<EvalParams>>) {
+		self.psqt = Psqt::scan(self, params.as_deref().unwrap_or(&PARAMS_DEFAULT));
+		self.psqt_params = params;
+		self.stored_value.set(None);
+	}
+
+	/// Material and piece-square scores as (middlegame, endgame) per color
+	pub fn material_pst(&self, params: &EvalParams) -> ([Weight; 2], [Weight; 2]) {
+		// the parameters are recognised by address
+		let psqt = if std::ptr::eq(params, self.psqt_params()) {
+			debug_assert_eq!(Psqt::scan(self, params), self.psqt);
+			self.psqt
+		} else {
+			Psqt::scan(self, params)
+		};
+		(psqt.material, psqt.pst)
+	}
+
+	/// Classic evaluation with the given parameters, or the score of a known endgame: the total of the trace.
+	/// The result is kept in the board, which is only ever evaluated with one set of parameters.
+	/// Only the material, piece-square and phase terms are incremental: the pawn structure
+	/// comes from the pawn hash table, and mobility, king safety and the piece terms still
+	/// need the moves of each piece. `Simplified` evaluates with the incremental terms alone
+	pub fn value_with(&self, params: &EvalParams) -> Value {
 		if let Some(v) = self.stored_value.get() {
 			return v;
 		}
-		let mut v = 0;
-		for c in 0..8 {
-			for r in 0..8 {
-				let pos = Pos::at(c, r).unwrap();
-				if let Some(tile) = self.at(pos) {
-					match tile.color {
-						Color::Black => {
-							v -= tile.value();
-							v -= MG_POS_BONUS[tile.piece as usize][7-r as usize][7-c as usize];
-						},
-						Color::White => {
-							v += tile.value();
-							v += MG_POS_BONUS[tile.piece as usize][r as usize][c as usize];
-						}
-					}
-				}
-			}
-		}
+		let v = self.trace(params).total;
 		self.stored_value.set(Some(v));
 		v
 	}
 }
+
+impl Valuable for Board {
+	/// Classic evaluation with the built-in parameters
+	fn value(&self) -> Value {
+		self.value_with(&PARAMS_DEFAULT)
+	}
+}
+
+/// A static evaluation function, used by the searches at the leaves and for move ordering
+pub trait Evaluator: Send + Sync + fmt::Debug {
+	/// Score of the position from White's point of view
+	fn evaluate(&self, b: &Board) -> Value;
+
+	/// Prepares the root of a search, e.g. with state to be updated incrementally by `make_move`
+	fn attach(&self, _b: &mut Board) {}
+
+	/// Breaks the score of the position into its terms
+	fn trace(&self, b: &Board) -> EvalTrace;
+}
+
+/// The hand-crafted evaluation of `Valuable for Board`
+#[derive(Debug)]
+pub struct Classic {
+	/// The built-in parameters if not set
+	params: Option<Arc<EvalParams>>,
+}
+impl Evaluator for Classic {
+	#[inline]
+	fn evaluate(&self, b: &Board) -> Value {
+		b.value_with(self.params.as_deref().unwrap_or(&PARAMS_DEFAULT))
+	}
+
+	fn attach(&self, b: &mut Board) {
+		b.set_psqt_params(self.params.clone());
+	}
+
+	fn trace(&self, b: &Board) -> EvalTrace {
+		b.trace(self.params.as_deref().unwrap_or(&PARAMS_DEFAULT))
+	}
+}
+
+/// Material and tapered piece-square tables, without any further knowledge
+#[derive(Debug)]
+pub struct Simplified {
+	/// The built-in parameters if not set
+	params: Option<Arc<EvalParams>>,
+}
+impl Evaluator for Simplified {
+	fn evaluate(&self, b: &Board) -> Value {
+		if let Some(v) = b.endgame_value() {
+			return v;
+		}
+		let (material, pst) = b.material_pst(self.params.as_deref().unwrap_or(&PARAMS_DEFAULT));
+		let (white, black) = (Color::White as usize, Color::Black as usize);
+		let mg = material[white].0 + pst[white].0 - material[black].0 - pst[black].0;
+		let eg = material[white].1 + pst[white].1 - material[black].1 - pst[black].1;
+		taper(mg, eg, b.game_phase())
+	}
+
+	fn attach(&self, b: &mut Board) {
+		b.set_psqt_params(self.params.clone());
+	}
+
+	fn trace(&self, b: &Board) -> EvalTrace {
+		let (material, pst) = b.material_pst(self.params.as_deref().unwrap_or(&PARAMS_DEFAULT));
+		let mut trace = EvalTrace { evaluation: Evaluation::Simplified, material, pst, phase: b.game_phase(), ..Default::default() };
+		trace.set_total(b);
+		trace
+	}
+}
+
+pub fn new_evaluator(opts: &Options) -> Arc<dyn Evaluator> {
+	let params = opts.params.clone();
+	match opts.eval {
+		Evaluation::Classic => Arc::new(Classic { params }),
+		Evaluation::Simplified => Arc::new(Simplified { params }),
+		Evaluation::Nnue => match &opts.nnue {
+			Some(net) => Arc::new(Nnue::new(net.clone())),
+			// no network loaded
+			None => Arc::new(Classic { params }),
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::board::{Board,Move};
+	use std::sync::Arc;
+	use crate::evaluation::{Psqt,Valuable,MAX_PHASE,new_evaluator};
+	use crate::misc::{Evaluation,OPTS_DEFAULT};
+	use crate::params::PARAMS_DEFAULT;
+
+	#[test]
+	pub fn test_game_phase() {
+		let b = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0").unwrap();
+		debug_assert_eq!(b.game_phase(), MAX_PHASE);
+		let b = Board::from_fen("8/4k3/8/8/8/8/4P3/4K3 w - - 0 0").unwrap();
+		debug_assert_eq!(b.game_phase(), 0);
+	}
+
+	#[test]
+	pub fn test_incremental_terms() {
+		let mut b = Board::from_fen("r3k2r/1P6/8/8/8/5p2/4P3/R3K2R w KQkq - 0 1").unwrap();
+		// castling on both sides, capture, promotion with capture
+		for mv in ["e1c1", "e8g8", "e2f3", "g8h8", "b7a8"] {
+			b = b.clone_apply_move(&mv.parse::<Move>().unwrap());
+			debug_assert_eq!(b.psqt, Psqt::scan(&b, &PARAMS_DEFAULT));
+		}
+		debug_assert_eq!(b.value(), b.trace(&PARAMS_DEFAULT.clone()).total);
+
+		// other parameters, once attached, are kept up to date instead of the built-in ones
+		let mut params = PARAMS_DEFAULT.clone();
+		params.piece_value[1] = (400, 400);
+		params.pst[1][18] = (50, 50);
+		let params = Arc::new(params);
+		let mut opts = OPTS_DEFAULT.clone();
+		opts.eval = Evaluation::Simplified;
+		opts.params = Some(params.clone());
+		let mut b = Board::from_fen("r1bqkbnr/pppppppp/2n5/8/8/5N2/PPPPPPPP/RNBQKB1R w KQkq - 0 1").unwrap();
+		new_evaluator(&opts).attach(&mut b);
+		for mv in ["b1c3", "c6d4", "f3d4"] {
+			b = b.clone_apply_move(&mv.parse::<Move>().unwrap());
+			debug_assert_eq!(b.psqt, Psqt::scan(&b, &params));
+		}
+		debug_assert_ne!(b.material_pst(&params), b.material_pst(&PARAMS_DEFAULT));
+	}
+
+	#[test]
+	pub fn test_endgame_king_centralization() {
+		let center = Board::from_fen("8/4k3/8/8/3K4/8/4P3/8 w - - 0 0").unwrap();
+		let corner = Board::from_fen("8/4k3/8/8/8/8/4P3/K7 w - - 0 0").unwrap();
+		debug_assert!(center.value() > corner.value());
+	}
+
+	#[test]
+	pub fn test_evaluators() {
+		let b = Board::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 0").unwrap();
+		let mut opts = OPTS_DEFAULT.clone();
+		debug_assert_eq!(new_evaluator(&opts).evaluate(&b), b.value());
+		let material_only = Board::from_fen("4kn2/8/8/8/8/8/8/2BQK3 w - - 0 0").unwrap();
+		opts.eval = Evaluation::Simplified;
+		debug_assert!(new_evaluator(&opts).evaluate(&material_only) > 800);
+		let mut params = PARAMS_DEFAULT.clone();
+		params.piece_value[4] = (0, 0);
+		opts.params = Some(Arc::new(params));
+		debug_assert!(new_evaluator(&opts).evaluate(&material_only) < 100);
+	}
+}
@@ -96,6 +96,26 @@ impl Board {
 		None
 	}
 
+	/// Whether the given color has any piece other than pawns and king.
+	/// Without those, zugzwang positions are common
+	pub fn has_non_pawn_material(&self, color: Color) -> bool {
+		self.tiles.iter().flatten().any(|t| match t {
+			Some(tile) => tile.color == color && tile.piece != Piece::Pawn && tile.piece != Piece::King,
+			None => false
+		})
+	}
+
+	/// Whether the move is neither a capture nor a promotion
+	pub fn is_quiet_move(&self, mv: &Move) -> bool {
+		if self.at(mv.t_pos).is_some() {
+			return false;
+		}
+		match self.at(mv.f_pos) {
+			Some(f_tile) if f_tile.piece == Piece::Pawn => mv.t_pos.row != 0 && mv.t_pos.row != 7,
+			_ => true
+		}
+	}
+
 	pub fn move_type(&self, f_pos: Pos, t_pos: Pos) -> MoveType {
 		if let Some(f_tile) = self.at(f_pos) {
 			if let Some(t_tile) = self.at(t_pos) {
@@ -311,7 +331,10 @@ impl Board {
 	}
 }
 
+#[cfg(test)]
 mod tests {
+	use crate::board::Board;
+
 	#[test]
 	pub fn test_is_king_in_check() {
 		let fen = "rnbqk1nr/pppp1ppp/8/8/1bPp4/6P1/PP2PP1P/RNBQKBNR w KQkq - 0 0";
@@ -0,0 +1,166 @@
+use std::sync::OnceLock;
+
+use crate::board::Color;
+
+/*
+ * King and pawn versus king bitbase: https://www.chessprogramming.org/KPK
+ * Generated on first use by retrograde analysis, one bit per position telling whether the
+ * strong side wins. The strong side is taken to be White, with the pawn on files a to d.
+ * Squares are numbered from a1 = 0 to h8 = 63.
+*/
+
+/// Side to move × pawn square (files a-d, ranks 2-7) × strong king × weak king
+const MAX_INDEX: usize = 2 * 24 * 64 * 64;
+
+#[inline]
+fn index(white_to_move: bool, weak_king: usize, strong_king: usize, pawn: usize) -> usize {
+	strong_king | (weak_king << 6) | ((!white_to_move as usize) << 12) | ((pawn & 7) << 13) | ((6 - (pawn >> 3)) << 15)
+}
+
+#[inline]
+fn distance(a: usize, b: usize) -> usize {
+	let dr = ((a >> 3) as i32 - (b >> 3) as i32).abs();
+	let df = ((a & 7) as i32 - (b & 7) as i32).abs();
+	dr.max(df) as usize
+}
+
+/// Squares a king on `sq` moves to
+fn king_moves(sq: usize) -> impl Iterator<Item = usize> {
+	(0..64).filter(move |s| distance(sq, *s) == 1)
+}
+
+/// Whether a white pawn on `pawn` attacks `sq`
+#[inline]
+fn pawn_attacks(pawn: usize, sq: usize) -> bool {
+	sq >> 3 == (pawn >> 3) + 1 && ((sq & 7) as i32 - (pawn & 7) as i32).abs() == 1
+}
+
+/* Results as flags, so that those of the successors can be or-ed together */
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+/// Decodes an index into (white to move, weak king, strong king, pawn)
+fn decode(idx: usize) -> (bool, usize, usize, usize) {
+	let strong_king = idx & 63;
+	let weak_king = (idx >> 6) & 63;
+	let white_to_move = (idx >> 12) & 1 == 0;
+	let pawn = (6 - ((idx >> 15) & 7)) * 8 + ((idx >> 13) & 3);
+	(white_to_move, weak_king, strong_king, pawn)
+}
+
+/// Result of a position before looking at its successors
+fn initial(idx: usize) -> u8 {
+	let (white_to_move, bk, wk, pawn) = decode(idx);
+	let push = pawn + 8;
+	if distance(wk, bk) <= 1 || wk == pawn || bk == pawn || (white_to_move && pawn_attacks(pawn, bk)) {
+		INVALID
+	} else if white_to_move && pawn >> 3 == 6 && wk != push && (distance(bk, push) > 1 || distance(wk, push) == 1) {
+		// the pawn promotes safely
+		WIN
+	} else if !white_to_move
+		&& (king_moves(bk).all(|s| distance(s, wk) <= 1 || pawn_attacks(pawn, s))
+			|| (distance(bk, pawn) == 1 && distance(wk, pawn) > 1)) {
+		// stalemate, or the pawn is lost
+		DRAW
+	} else {
+		UNKNOWN
+	}
+}
+
+/// Result of a position from those of its successors: White needs one winning move,
+/// Black one drawing move
+fn classify(db: &[u8], idx: usize) -> u8 {
+	let (white_to_move, bk, wk, pawn) = decode(idx);
+	let mut r = INVALID;
+	if white_to_move {
+		for s in king_moves(wk) {
+			r |= db[index(false, bk, s, pawn)];
+		}
+		if pawn >> 3 < 6 {
+			r |= db[index(false, bk, wk, pawn + 8)];
+		}
+		if pawn >> 3 == 1 && pawn + 8 != wk && pawn + 8 != bk {
+			r |= db[index(false, bk, wk, pawn + 16)];
+		}
+	} else {
+		for s in king_moves(bk) {
+			r |= db[index(true, s, wk, pawn)];
+		}
+	}
+	let (good, bad) = if white_to_move { (WIN, DRAW) } else { (DRAW, WIN) };
+	if r & good != 0 {
+		good
+	} else if r & UNKNOWN != 0 {
+		UNKNOWN
+	} else {
+		bad
+	}
+}
+
+/// One bit per position, set when White wins
+fn bitbase() -> &'static [u32] {
+	static KPK: OnceLock<Vec<u32>> = OnceLock::new();
+	KPK.get_or_init(|| {
+		let mut db: Vec<u8> = (0..MAX_INDEX).map(initial).collect();
+		let mut changed = true;
+		while changed {
+			changed = false;
+			for idx in 0..MAX_INDEX {
+				if db[idx] == UNKNOWN {
+					let r = classify(&db, idx);
+					if r != UNKNOWN {
+						db[idx] = r;
+						changed = true;
+					}
+				}
+			}
+		}
+		let mut bits = vec![0u32; MAX_INDEX / 32];
+		for (idx, r) in db.iter().enumerate() {
+			if *r == WIN {
+				bits[idx / 32] |= 1 << (idx % 32);
+			}
+		}
+		bits
+	})
+}
+
+/// Whether the side with the pawn wins. Squares are `row * 8 + col`
+pub fn probe(strong_king: usize, pawn: usize, weak_king: usize, strong: Color, to_move: Color) -> bool {
+	// from White's side, with the pawn on files a-d
+	let flip_ranks = if strong == Color::White { 0 } else { 56 };
+	let flip_files = if pawn & 7 > 3 { 7 } else { 0 };
+	let normalize = |sq: usize| sq ^ flip_ranks ^ flip_files;
+	let idx = index(to_move == strong, normalize(weak_king), normalize(strong_king), normalize(pawn));
+	bitbase()[idx / 32] & (1 << (idx % 32)) != 0
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::board::Color;
+	use crate::kpk::probe;
+
+	/// Square index of a coordinate such as `e4`
+	fn sq(coord: &str) -> usize {
+		let c = coord.as_bytes();
+		(c[1] - b'1') as usize * 8 + (c[0] - b'a') as usize
+	}
+
+	#[test]
+	pub fn test_kpk() {
+		// opposition decides
+		debug_assert!(!probe(sq("e5"), sq("e4"), sq("e7"), Color::White, Color::White));
+		debug_assert!(probe(sq("e5"), sq("e4"), sq("e7"), Color::White, Color::Black));
+		// king on the sixth rank in front of the pawn
+		debug_assert!(probe(sq("e6"), sq("e5"), sq("e8"), Color::White, Color::White));
+		// rook pawn with the defending king in the corner
+		debug_assert!(!probe(sq("c1"), sq("a2"), sq("a8"), Color::White, Color::White));
+		// the same from Black's side, on the other wing
+		debug_assert!(probe(sq("d4"), sq("d5"), sq("d2"), Color::Black, Color::White));
+		debug_assert!(!probe(sq("d4"), sq("d5"), sq("d2"), Color::Black, Color::Black));
+		// the pawn runs away
+		debug_assert!(probe(sq("a1"), sq("g5"), sq("a8"), Color::White, Color::White));
+	}
+}
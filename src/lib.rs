@@ -1,29 +1,98 @@
 extern crate wasm_bindgen;
+use std::cell::RefCell;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use clap::ColorChoice;
 use wasm_bindgen::prelude::*;
 
 mod board;
+mod book;
+mod endgame;
 mod evaluation;
 mod inspection;
+mod kpk;
 mod generation;
 mod misc;
-// mod minimax;
-// mod negamax;
+mod minimax;
+mod negamax;
 mod negascout;
+mod nnue;
 mod ordering;
+mod params;
+mod pawns;
+// mod pgn;
+mod positional;
+mod san;
+mod search;
+mod syzygy;
+mod trace;
+mod tt;
 // mod uci;
+mod zobrist;
 use crate::board::{Board,Pos, Move, MoveType, GameEnd, Color};
-use crate::negascout::negascout;
-use crate::misc::OPTS_DEFAULT;
+use crate::search::{Searcher, new_searcher};
+use crate::misc::{Evaluation, Options, OPTS_DEFAULT, SearchAlgorithm, SearchResult};
+use crate::evaluation::{mate_distance, new_evaluator};
+use crate::book::Book;
+use crate::nnue::Network;
+use crate::params::EvalParams;
+
+thread_local! {
+  /// Evaluation parameters set with `set_eval_params`, the built-in ones if none
+  static EVAL_PARAMS: RefCell<Option<Arc<EvalParams>>> = RefCell::new(None);
+  /// Network set with `set_nnue_weights`, which selects the neural network evaluation
+  static NNUE: RefCell<Option<Arc<Network>>> = RefCell::new(None);
+  /// Opening book set with `set_opening_book`
+  static BOOK: RefCell<Option<Arc<Book>>> = RefCell::new(None);
+  /// Searcher kept between moves, so that its transposition table is allocated once
+  /// and keeps what it learned. Replaced when the options change
+  static SEARCHER: RefCell<Option<(Options, Box<dyn Searcher + Send>)>> = RefCell::new(None);
+}
+
+/// Search options of the Wasm API
+fn wasm_options() -> Options {
+  let mut opts = OPTS_DEFAULT.clone();
+  opts.search_algo = SearchAlgorithm::Negascout;
+  opts.params = EVAL_PARAMS.with(|p| p.borrow().clone());
+  opts.nnue = NNUE.with(|n| n.borrow().clone());
+  if opts.nnue.is_some() {
+    opts.eval = Evaluation::Nnue;
+  }
+  opts.book = BOOK.with(|b| b.borrow().clone());
+  opts.own_book = opts.book.is_some();
+  opts
+}
+
+/// Searches the board with the long-lived searcher, reporting `multi_pv` lines
+fn search(b: &Board, multi_pv: usize) -> Option<SearchResult> {
+  let opts = wasm_options();
+  SEARCHER.with(|s| {
+    let mut s = s.borrow_mut();
+    if s.as_ref().is_none_or(|(o, _)| *o != opts) {
+      *s = Some((opts.clone(), new_searcher(&opts)));
+    }
+    let searcher = &mut s.as_mut()?.1;
+    searcher.state_mut().opts.multi_pv = multi_pv;
+    searcher.search(b)
+  })
+}
+
+/// Reply to an input that couldn't be parsed: `illegal_input: ` followed by the reason
+fn illegal_input(e: impl std::fmt::Display) -> String {
+  format!("illegal_input: {}", e)
+}
 
 #[wasm_bindgen]
-/// Computes the best move from the given board
+/// Computes the best move from the given board.
+/// Returns the new FEN, or `illegal_input: <reason>` when the FEN is rejected
 pub fn find_best_move(fromFEN:&str) -> String {
-  let b = Board::from_fen(fromFEN).unwrap();
-  if let Some((_, mv)) = negascout(&b, &OPTS_DEFAULT) {
-    return b.clone_apply_move(&mv).to_fen();
+  let b = match Board::from_fen(fromFEN) {
+    Err(e) => return illegal_input(e),
+    Ok(v) => v
+  };
+  if let Some(res) = search(&b, 1) {
+    return b.clone_apply_move(&res.best_move).to_fen();
   }
   // This probably means the game has ended.
   // We don't really have a FEN to return, so we return the original FEN.
@@ -31,19 +100,118 @@ pub fn find_best_move(fromFEN:&str) -> String {
   fromFEN.to_string()
 }
 
+#[wasm_bindgen]
+/// Computes the `n` best moves from the given board.
+/// Returns a JSON array of candidates, best first, each with its move,
+/// its score (in centipawns, from White's point of view; `mate` is set instead
+/// when a forced mate is found, in moves, negative when the side to move gets mated)
+/// and its principal variation, in coordinates (`pv`) and in algebraic notation (`san`).
+/// An empty array means the game has ended.
+pub fn find_best_moves(fromFEN:&str, n:usize) -> String {
+  let b = match Board::from_fen(fromFEN) {
+    Err(e) => return illegal_input(e),
+    Ok(v) => v
+  };
+  let lines = match search(&b, n.max(1)) {
+    Some(res) => res.lines,
+    None => Vec::new()
+  };
+  let candidates: Vec<String> = lines.iter().map(|line| {
+    let pv: Vec<String> = line.pv.iter().map(|mv| format!("\"{}\"", mv)).collect();
+    let san: Vec<String> = b.line_to_san(&line.pv).iter().map(|mv| format!("\"{}\"", mv)).collect();
+    let stm_score = if b.player == Color::White { line.score } else { -line.score };
+    let score = match mate_distance(stm_score) {
+      Some(m) => format!("\"mate\":{}", m),
+      None => format!("\"score\":{}", line.score),
+    };
+    format!("{{\"move\":\"{}\",{},\"pv\":[{}],\"san\":[{}]}}", line.pv[0], score, pv.join(","), san.join(","))
+  }).collect();
+  format!("[{}]", candidates.join(","))
+}
+
+#[wasm_bindgen]
+/// Breaks the evaluation in use (`eval`: classic, simplified or nnue) of the given board into its terms.
+/// Returns a JSON object with, for each term, the `white` and `black` contributions
+/// as [middlegame, endgame] pairs in centipawns, plus the game `phase` (from
+/// `max_phase` in the opening down to 0) and the `total`, from White's point of view.
+/// The simplified evaluation only has material and piece-square terms, the network none.
+pub fn eval_trace(fromFEN:&str) -> String {
+  match Board::from_fen(fromFEN) {
+    Err(e) => illegal_input(e),
+    Ok(b) => new_evaluator(&wasm_options()).trace(&b).to_json()
+  }
+}
+
+#[wasm_bindgen]
+/// Replaces the evaluation parameters with those in the given text,
+/// in the format of `get_eval_params`. An empty text restores the built-in ones.
+/// Returns "ok", or the reason why the parameters were rejected
+pub fn set_eval_params(text:&str) -> String {
+  let params = if text.trim().is_empty() {
+    None
+  } else {
+    match EvalParams::from_text(text) {
+      Err(e) => return e,
+      Ok(p) => Some(Arc::new(p))
+    }
+  };
+  EVAL_PARAMS.with(|p| *p.borrow_mut() = params);
+  "ok".to_string()
+}
+
+#[wasm_bindgen]
+/// Loads the weights of the neural network evaluation and selects it.
+/// Returns "ok", or the reason the weights were rejected.
+/// Empty weights go back to the classic evaluation
+pub fn set_nnue_weights(bytes:&[u8]) -> String {
+  let net = if bytes.is_empty() {
+    None
+  } else {
+    match Network::from_bytes(bytes) {
+      Err(e) => return e,
+      Ok(n) => Some(Arc::new(n))
+    }
+  };
+  NNUE.with(|n| *n.borrow_mut() = net);
+  "ok".to_string()
+}
+
+#[wasm_bindgen]
+/// Loads a Polyglot opening book, used by `find_best_move` while it has moves for the position.
+/// Returns "ok", or the reason the book was rejected.
+/// An empty book stops using it
+pub fn set_opening_book(bytes:&[u8]) -> String {
+  let book = if bytes.is_empty() {
+    None
+  } else {
+    match Book::from_bytes(bytes) {
+      Err(e) => return e,
+      Ok(b) => Some(Arc::new(b))
+    }
+  };
+  BOOK.with(|b| *b.borrow_mut() = book);
+  "ok".to_string()
+}
+
+#[wasm_bindgen]
+/// Returns the evaluation parameters in use, as text
+pub fn get_eval_params() -> String {
+  wasm_options().eval_params().to_text()
+}
+
 #[wasm_bindgen]
 /// Checks that the move is legal e returns a new FEN for the opponent
 pub fn make_move(fromFEN:&str, fromPos:&str, toPos:&str) -> String {
   let b = match Board::from_fen(fromFEN) {
-    Err(_) => return "illegal_input".to_string(),
+    Err(e) => return illegal_input(e),
     Ok(v) => v
   };
   let f_pos = match Pos::from_str(fromPos) {
-    Err(_) => return "illegal_input".to_string(),
+    Err(e) => return illegal_input(e),
     Ok(v) => v
   };
   let t_pos = match Pos::from_str(toPos) {
-    Err(_) => return "illegal_input".to_string(),
+    Err(e) => return illegal_input(e),
     Ok(v) => v
   };
   let mt = b.check_move(f_pos, t_pos, 0);
@@ -55,11 +223,42 @@ pub fn make_move(fromFEN:&str, fromPos:&str, toPos:&str) -> String {
   }
 }
 
+#[wasm_bindgen]
+/// Plays a move written in algebraic notation, e.g. `Nf3` or `O-O`.
+/// Returns the new FEN, or `illegal_input: <reason>` when the FEN or the move is rejected
+pub fn make_san_move(fromFEN:&str, san:&str) -> String {
+  let b = match Board::from_fen(fromFEN) {
+    Err(e) => return illegal_input(e),
+    Ok(v) => v
+  };
+  match b.parse_san(san) {
+    Ok(mv) => b.clone_apply_move(&mv).to_fen(),
+    Err(e) => illegal_input(e)
+  }
+}
+
+#[wasm_bindgen]
+/// Writes a move given by its squares in algebraic notation, e.g. `Nf3`, or "illegal"
+pub fn move_to_san(fromFEN:&str, fromPos:&str, toPos:&str) -> String {
+  let b = match Board::from_fen(fromFEN) {
+    Err(e) => return illegal_input(e),
+    Ok(v) => v
+  };
+  let (f_pos, t_pos) = match (Pos::from_str(fromPos), Pos::from_str(toPos)) {
+    (Ok(f), Ok(t)) => (f, t),
+    (Err(e), _) | (_, Err(e)) => return illegal_input(e)
+  };
+  match b.check_move(f_pos, t_pos, 0) {
+    MoveType::Illegal => "illegal".to_string(),
+    _ => b.move_to_san(&Move{f_pos, t_pos})
+  }
+}
+
 #[wasm_bindgen]
 /// Reports whether and in what way the game ended
 pub fn check_end_game(fromFEN:&str) -> String {
   let b = match Board::from_fen(fromFEN) {
-    Err(_) => return "illegal_input".to_string(),
+    Err(e) => return illegal_input(e),
     Ok(v) => v
   };
   match b.check_end_game() {
@@ -68,4 +267,18 @@ pub fn check_end_game(fromFEN:&str) -> String {
     Some(GameEnd::Checkmate(Color::White)) => return "checkmate white".to_string(),
     None => return "none".to_string()
   };
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use crate::make_san_move;
+
+  #[test]
+  pub fn test_make_san_move() {
+    let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    debug_assert_eq!(make_san_move(start, "Nf3"), "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 0 0");
+    // rejected moves and FENs get the same prefix
+    debug_assert_eq!(make_san_move(start, "Ke2"), "illegal_input: illegal move");
+    debug_assert_eq!(make_san_move(start, "Zz9"), "illegal_input: invalid move syntax");
+    debug_assert!(make_san_move("8/8/8 w - - 0 1", "Nf3").starts_with("illegal_input: "));
+  }
+}
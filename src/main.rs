@@ -1,42 +1,78 @@
 use board::GameEnd;
-use clap::{Command, Arg};
+use clap::{Command, Arg, ArgMatches};
 
 mod board;
+mod book;
+mod book_build;
+mod endgame;
 mod evaluation;
 mod inspection;
+mod kpk;
 mod generation;
 mod minimax;
 mod misc;
 mod negamax;
 mod negascout;
+mod nnue;
 mod ordering;
+mod params;
+mod pawns;
+mod pgn;
+mod positional;
+mod san;
+mod search;
+mod syzygy;
+mod trace;
+mod tt;
+mod tune;
 mod uci;
+mod zobrist;
 
-use crate::board::{Board,Color,Move};
-use crate::minimax::minimax;
-use crate::negamax::negamax;
-use crate::negascout::negascout;
-use crate::evaluation::{Value};
+use std::fs;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::board::{Board,Color};
+use crate::evaluation::new_evaluator;
+use crate::book::Book;
+use crate::book_build::BuildLimits;
+use crate::syzygy::Tablebases;
+use crate::pgn::{GameRecord, load_games, read_games};
+use crate::nnue::Network;
+use crate::params::EvalParams;
+use crate::tune::{Tuner, load_positions};
+use crate::search::new_searcher;
 use crate::uci::{uci_manager};
 use crate::misc::*;
 
 
 extern crate vampirc_uci;
 
-fn self_play_test(opts: &Options) {
+fn self_play_test(opts: &Options, pgn_out: Option<&str>) {
 	let mut b: Board = Board::new(Color::White);
+	let mut searcher = new_searcher(opts);
+	let mut game = GameRecord::new(&b);
+	game.set_tag("Event", "Duchess self-play");
+	game.set_tag("White", "Duchess");
+	game.set_tag("Black", "Duchess");
+	game.set_options(opts);
 	/* Just for testing: AI playing against itself in a loop */
 
 	for i in 0..100 {
-		if let Some(res) = match opts.search_algo {
-			SearchAlgorithm::Minimax => minimax(&b, opts),
-			SearchAlgorithm::Negamax => negamax(&b, opts),
-			SearchAlgorithm::Negascout => negascout(&b, opts),
-			// _ => panic!("Algorithm {:?} not supported", opts.search_algo)
-		} {
-			let score = res.0;
-			let mv = res.1;
-			println!("{}. {:?}: [{}{}]({})", i, b.player, mv.f_pos, mv.t_pos, score);
+		if let Some(res) = searcher.search(&b) {
+			let mv = res.best_move;
+			let pv = b.line_to_san(&res.pv);
+			println!("{}. {:?}: [{}]({}) depth {} nodes {} time {}ms researches {} pv {}",
+				i, b.player, b.move_to_san(&mv), res.score, res.depth, res.nodes, res.time_ms, res.researches, pv.join(" "));
+			if res.lines.len() > 1 {
+				for (k, line) in res.lines.iter().enumerate() {
+					let pv = b.line_to_san(&line.pv);
+					println!("    {}. ({}) {}", k + 1, line.score, pv.join(" "));
+				}
+			}
+			// book and tablebase moves are not searched
+			game.push(mv, if res.depth > 0 { Some(res.score) } else { None }, res.depth);
 			b = b.clone_apply_move(&mv);
 		} else {
 			let result = match b.check_end_game() {
@@ -49,6 +85,97 @@ fn self_play_test(opts: &Options) {
 			break;
 		}
 	}
+	if let Some(path) = pgn_out {
+		game.finish("*");
+		let written = fs::OpenOptions::new().create(true).append(true).open(path)
+			.and_then(|mut f| f.write_all(game.to_pgn(true).as_bytes()));
+		if let Err(e) = written {
+			eprintln!("{}: {}", path, e);
+		}
+	}
+}
+
+fn tune(opts: &Options, args: &ArgMatches) {
+	let positions = match load_positions(args.value_of("positions").unwrap()) {
+		Ok(p) => p,
+		Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
+	};
+	let output = args.value_of("output").unwrap();
+	let iterations = args.value_of("iterations").and_then(|v| v.parse::<usize>().ok()).unwrap_or(100);
+	println!("{} positions", positions.len());
+
+	let mut tuner = Tuner::new(positions, opts.threads);
+	let k = tuner.fit_k(opts.eval_params());
+	println!("K = {:.3}, initial error {:.6}", k, tuner.error(opts.eval_params()));
+	tuner.tune(opts.eval_params(), iterations, |it, err, params| {
+		println!("iteration {}: error {:.6}", it, err);
+		if let Err(e) = fs::write(output, params.to_text()) {
+			eprintln!("{}: {}", output, e);
+		}
+	});
+	println!("Tuned parameters written to {}", output);
+}
+
+/// Value of a numeric argument with a default, exiting with an error when it is malformed
+fn number_arg<T: FromStr>(args: &ArgMatches, name: &str) -> T {
+	let value = args.value_of(name).unwrap();
+	value.parse().unwrap_or_else(|_| {
+		eprintln!("--{}: invalid number '{}'", name, value);
+		std::process::exit(1);
+	})
+}
+
+fn build_book(args: &ArgMatches) {
+	let mut games = Vec::new();
+	for path in args.values_of("pgn").unwrap() {
+		match fs::read_to_string(path) {
+			Ok(text) => games.extend(read_games(&text)),
+			Err(e) => {
+				eprintln!("{}: {}", path, e);
+				std::process::exit(1);
+			}
+		}
+	}
+	let limits = BuildLimits {
+		max_ply: number_arg(args, "max-ply"),
+		min_games: number_arg(args, "min-games"),
+		min_score: number_arg(args, "min-score"),
+	};
+	let (book, errors) = Book::build(&games, &limits);
+	for e in errors {
+		eprintln!("{}", e);
+	}
+	let output = args.value_of("output").unwrap();
+	if let Err(e) = fs::write(output, book.to_bytes()) {
+		eprintln!("{}: {}", output, e);
+		std::process::exit(1);
+	}
+	println!("{} games, {} book entries written to {}", games.len(), book.entry_count(), output);
+}
+
+fn check_pgn(args: &ArgMatches) {
+	let mut failed = false;
+	for path in args.values_of("pgn").unwrap() {
+		let text = match fs::read_to_string(path) {
+			Ok(text) => text,
+			Err(e) => {
+				eprintln!("{}: {}", path, e);
+				std::process::exit(1);
+			}
+		};
+		let (games, errors) = load_games(&text);
+		for e in &errors {
+			eprintln!("{}: {}", path, e);
+		}
+		println!("{}: {} valid games, {} invalid", path, games.len(), errors.len());
+		failed |= !errors.is_empty();
+	}
+	if failed {
+		std::process::exit(1);
+	}
 }
 
 fn main() {
@@ -84,6 +211,50 @@ fn main() {
 				.default_value("eval")
 				.help("Move ordering strategy"),
 		)
+		.arg(
+			Arg::new("eval")
+				.short('e')
+				.long("eval")
+				.takes_value(true)
+				.possible_values(&["classic", "simplified", "nnue"])
+				.default_value("classic")
+				.help("Static evaluation function (nnue requires --nnue)"),
+		)
+		.arg(
+			Arg::new("nnue")
+				.long("nnue")
+				.takes_value(true)
+				.help("File with the weights of the neural network evaluation. Selects --eval nnue unless given otherwise"),
+		)
+		.arg(
+			Arg::new("eval-params")
+				.long("eval-params")
+				.takes_value(true)
+				.help("File with the evaluation parameters (default: built-in)"),
+		)
+		.arg(
+			Arg::new("book")
+				.long("book")
+				.takes_value(true)
+				.help("Polyglot opening book (.bin) to play from while it has moves for the position"),
+		)
+		.arg(
+			Arg::new("book-best")
+				.long("book-best")
+				.help("Always play the book move with the highest weight, instead of picking one at random by weight"),
+		)
+		.arg(
+			Arg::new("pgn-out")
+				.long("pgn-out")
+				.takes_value(true)
+				.help("Appends the games played in terminal mode to this PGN file, with {score/depth} comments"),
+		)
+		.arg(
+			Arg::new("syzygy")
+				.long("syzygy")
+				.takes_value(true)
+				.help("Directories with Syzygy endgame tablebases, separated by ':'"),
+		)
 		.arg(
 			Arg::new("depth")
 				.short('d')
@@ -97,6 +268,136 @@ fn main() {
 				.long("no-alphabeta")
 				.help("Disable alpha-beta pruning"),
 		)
+		.arg(
+			Arg::new("no-null-move")
+				.long("no-null-move")
+				.help("Disable null-move pruning (negascout only)"),
+		)
+		.arg(
+			Arg::new("no-lmr")
+				.long("no-lmr")
+				.help("Disable late move reductions (negascout only)"),
+		)
+		.arg(
+			Arg::new("no-aspiration")
+				.long("no-aspiration")
+				.help("Disable aspiration windows (negascout only)"),
+		)
+		.arg(
+			Arg::new("threads")
+				.short('t')
+				.long("threads")
+				.takes_value(true)
+				.default_value("1")
+				.help("Number of search threads (negascout only)"),
+		)
+		.arg(
+			Arg::new("hash")
+				.long("hash")
+				.takes_value(true)
+				.default_value("16")
+				.help("Size of the transposition table, in megabytes"),
+		)
+		.arg(
+			Arg::new("multi-pv")
+				.long("multi-pv")
+				.takes_value(true)
+				.default_value("1")
+				.help("Number of best moves to report, each with its own line"),
+		)
+		.subcommand(
+			Command::new("tune")
+				.about("Tunes the evaluation parameters on a set of quiet positions, with Texel's method. Starts from --eval-params and uses --threads")
+				.arg(
+					Arg::new("positions")
+						.required(true)
+						.help("File with one position per line: a FEN and the game result (1-0, 0-1, 1/2-1/2 or [1.0], [0.0], [0.5])"),
+				)
+				.arg(
+					Arg::new("output")
+						.long("output")
+						.takes_value(true)
+						.default_value("tuned-params.toml")
+						.help("Where to write the tuned parameters, after each iteration"),
+				)
+				.arg(
+					Arg::new("iterations")
+						.long("iterations")
+						.takes_value(true)
+						.default_value("100")
+						.help("Maximum number of passes over all the parameters"),
+				),
+		)
+		.subcommand(
+			Command::new("book")
+				.about("Opening book tools")
+				.subcommand_required(true)
+				.subcommand(
+					Command::new("build")
+						.about("Builds a Polyglot book from the games in PGN files, weighting moves by their results")
+						.arg(
+							Arg::new("pgn")
+								.required(true)
+								.multiple_values(true)
+								.help("PGN files with the games"),
+						)
+						.arg(
+							Arg::new("output")
+								.long("output")
+								.takes_value(true)
+								.default_value("book.bin")
+								.help("Where to write the book"),
+						)
+						.arg(
+							Arg::new("max-ply")
+								.long("max-ply")
+								.takes_value(true)
+								.default_value("20")
+								.help("Number of plies of each game to record"),
+						)
+						.arg(
+							Arg::new("min-games")
+								.long("min-games")
+								.takes_value(true)
+								.default_value("1")
+								.help("Leave out moves played in fewer games"),
+						)
+						.arg(
+							Arg::new("min-score")
+								.long("min-score")
+								.takes_value(true)
+								.default_value("0")
+								.help("Leave out moves with a lower average score (0 to 1) for the side playing them"),
+						),
+				),
+		)
+		.subcommand(
+			Command::new("pgn")
+				.about("PGN tools")
+				.subcommand_required(true)
+				.subcommand(
+					Command::new("check")
+						.about("Replays the games in PGN files, reporting invalid, illegal or ambiguous moves by game and ply")
+						.arg(
+							Arg::new("pgn")
+								.required(true)
+								.multiple_values(true)
+								.help("PGN files with the games"),
+						),
+				),
+		)
+		.subcommand(
+			Command::new("params")
+				.about("Prints the evaluation parameters in use, in the format read by --eval-params"),
+		)
+		.subcommand(
+			Command::new("eval")
+				.about("Prints the evaluation breakdown of a position")
+				.arg(
+					Arg::new("fen")
+						.help("The position to evaluate (default: the starting position)"),
+				),
+		)
 		.get_matches();
 
 		if let Some(m) = matches.value_of("ui") {
@@ -114,16 +415,120 @@ fn main() {
 				opts.move_ordering = val;
 			}
 		}
+		if let Some(m) = matches.value_of("eval") {
+			if let Ok(val) = m.parse::<Evaluation>() {
+				opts.eval = val;
+			}
+		}
+		if let Some(m) = matches.value_of("eval-params") {
+			match EvalParams::load(m) {
+				Ok(params) => opts.params = Some(Arc::new(params)),
+				Err(e) => {
+					eprintln!("{}", e);
+					std::process::exit(1);
+				}
+			}
+		}
+		if let Some(m) = matches.value_of("nnue") {
+			match Network::load(m) {
+				Ok(net) => opts.nnue = Some(Arc::new(net)),
+				Err(e) => {
+					eprintln!("{}", e);
+					std::process::exit(1);
+				}
+			}
+			if matches.occurrences_of("eval") == 0 {
+				opts.eval = Evaluation::Nnue;
+			}
+		}
+		if opts.eval == Evaluation::Nnue && opts.nnue.is_none() {
+			eprintln!("--eval nnue requires a network, see --nnue");
+			std::process::exit(1);
+		}
+		if let Some(m) = matches.value_of("book") {
+			match Book::load(m) {
+				Ok(book) => {
+					opts.book = Some(Arc::new(book));
+					opts.own_book = true;
+				},
+				Err(e) => {
+					eprintln!("{}", e);
+					std::process::exit(1);
+				}
+			}
+		}
+		opts.book_random = !matches.is_present("book-best");
+		if let Some(m) = matches.value_of("syzygy") {
+			match Tablebases::open(m) {
+				Ok(tb) => opts.syzygy = Some(Arc::new(tb)),
+				Err(e) => {
+					eprintln!("{}", e);
+					std::process::exit(1);
+				}
+			}
+		}
 		if let Some(m) = matches.value_of("depth") {
 			if let Ok(val) = m.parse::<u8>() {
 				opts.max_depth = val;
 			}
 		}
+		if let Some(m) = matches.value_of("threads") {
+			if let Ok(val) = m.parse::<usize>() {
+				opts.threads = val.max(1);
+			}
+		}
+		if let Some(m) = matches.value_of("hash") {
+			if let Ok(val) = m.parse::<usize>() {
+				opts.hash_mb = val.max(1);
+			}
+		}
+		if let Some(m) = matches.value_of("multi-pv") {
+			if let Ok(val) = m.parse::<usize>() {
+				opts.multi_pv = val.max(1);
+			}
+		}
 		opts.alpha_beta = !matches.is_present("no-alphabeta");
+		opts.null_move_pruning = !matches.is_present("no-null-move");
+		opts.late_move_reductions = !matches.is_present("no-lmr");
+		opts.aspiration = !matches.is_present("no-aspiration");
 		// println!("Options:\n {:#?}",opts);
+		if let Some(sub) = matches.subcommand_matches("eval") {
+			let b = match sub.value_of("fen") {
+				Some(fen) => match Board::from_fen(fen) {
+					Ok(b) => b,
+					Err(e) => {
+						eprintln!("{}: {}", e, fen);
+						std::process::exit(1);
+					}
+				},
+				None => Board::new(Color::White),
+			};
+			println!("{}", new_evaluator(&opts).trace(&b));
+			return;
+		}
+		if let Some(sub) = matches.subcommand_matches("tune") {
+			tune(&opts, sub);
+			return;
+		}
+		if let Some(sub) = matches.subcommand_matches("book") {
+			if let Some(build) = sub.subcommand_matches("build") {
+				build_book(build);
+			}
+			return;
+		}
+		if let Some(sub) = matches.subcommand_matches("pgn") {
+			if let Some(check) = sub.subcommand_matches("check") {
+				check_pgn(check);
+			}
+			return;
+		}
+		if matches.subcommand_matches("params").is_some() {
+			print!("{}", opts.eval_params().to_text());
+			return;
+		}
 		match opts.ui {
 			Ui::Uci => uci_manager(&opts.clone()),
-			Ui::AnsiTerm => self_play_test(&opts.clone()),
+			Ui::AnsiTerm => self_play_test(&opts.clone(), matches.value_of("pgn-out")),
 		}
 
 }
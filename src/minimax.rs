@@ -1,91 +1,145 @@
 use std::cmp;
 
 use crate::board::{Board, Color, Move};
-use crate::evaluation::{Valuable, Value};
+use crate::evaluation::{Value, mate_in, mated_in};
 use crate::misc::*;
 use crate::ordering::move_ordering;
+use crate::search::{Searcher, SearchState};
 
-pub fn minimax(
-	b: &Board,
-	opts: &Options,
-) -> Option<(Value, Move)> {
-	let res;
-	match b.player {
-		Color::Black => res = minimize(b, Value::MIN, Value::MAX, 0, opts),
-		Color::White => res = maximize(b, Value::MIN, Value::MAX, 0, opts),
-	}
-	if let (v, Some(mv)) = res {
-		return Some((v, mv));
-	}
-	None
+pub struct Minimax {
+	state: SearchState,
+	/* Depth of the current iteration */
+	horizon: u8,
 }
 
-fn maximize(
-	b: &Board,
-	mut alpha: Value,
-	beta: Value,
-	depth: u8,
-	opts: &Options,
-) -> (Value, Option<Move>) {
-	if depth == opts.max_depth {
-		return (b.value(), None);
+impl Minimax {
+	pub fn new(opts: &Options) -> Self {
+		Minimax { state: SearchState::new(opts), horizon: 0 }
 	}
 
-	let mut bs: Vec<(Move, Board)> = b.generate_all();
+	fn maximize(
+		&mut self,
+		b: &Board,
+		mut alpha: Value,
+		beta: Value,
+		depth: u8,
+	) -> (Value, Vec<Move>) {
+		if self.state.visit() {
+			return (0, Vec::new());
+		}
+		if depth == self.horizon {
+			return (self.state.eval.evaluate(b), Vec::new());
+		}
+
+		let mut bs: Vec<(Move, Board)> = b.generate_all();
+
+		if bs.is_empty() {
+			/* White is checkmated or stalemated */
+			return (if b.is_king_in_check(b.player) { mated_in(depth) } else { 0 }, Vec::new());
+		}
 
-	move_ordering(&mut bs, 1, opts);
+		move_ordering(&mut bs, 1, &self.state.opts, self.state.eval.as_ref());
+		if depth == 0 {
+			self.state.exclude_root_moves(&mut bs);
+		}
 
-	let mut best_score: Value = Value::MIN + 1;
-	let mut best_move = None;
-	for (mv, child) in bs.iter() {
-		let score = minimize(child, alpha, beta, depth + 1, opts).0;
-		if score > best_score {
-			best_score = score;
-			if depth == 0 {
-				best_move = Some(*mv);
+		let mut best_score: Value = Value::MIN + 1;
+		let mut pv = Vec::new();
+		for (mv, child) in bs.iter() {
+			let (score, child_pv) = self.minimize(child, alpha, beta, depth + 1);
+			if self.state.stopped() {
+				break;
 			}
-			if opts.alpha_beta {
-				alpha = cmp::max(alpha, best_score);
-				if alpha >= beta {
-					break;
+			if score > best_score {
+				best_score = score;
+				pv = vec![*mv];
+				pv.extend(child_pv);
+				if self.state.opts.alpha_beta {
+					alpha = cmp::max(alpha, best_score);
+					if alpha >= beta {
+						break;
+					}
 				}
 			}
 		}
+		(best_score, pv)
 	}
-	(best_score, best_move)
-}
 
-fn minimize(
-	b: &Board,
-	alpha: Value,
-	mut beta: Value,
-	depth: u8,
-	opts: &Options,
-) -> (Value, Option<Move>) {
-	if depth == opts.max_depth {
-		return (b.value(), None);
-	}
+	fn minimize(
+		&mut self,
+		b: &Board,
+		alpha: Value,
+		mut beta: Value,
+		depth: u8,
+	) -> (Value, Vec<Move>) {
+		if self.state.visit() {
+			return (0, Vec::new());
+		}
+		if depth == self.horizon {
+			return (self.state.eval.evaluate(b), Vec::new());
+		}
 
-	let mut bs: Vec<(Move, Board)> = b.generate_all();
+		let mut bs: Vec<(Move, Board)> = b.generate_all();
 
-	move_ordering(&mut bs, 1, opts);
+		if bs.is_empty() {
+			/* Black is checkmated or stalemated */
+			return (if b.is_king_in_check(b.player) { mate_in(depth) } else { 0 }, Vec::new());
+		}
+
+		move_ordering(&mut bs, 1, &self.state.opts, self.state.eval.as_ref());
+		if depth == 0 {
+			self.state.exclude_root_moves(&mut bs);
+		}
 
-	let mut best_score: Value = Value::MAX - 1;
-	let mut best_move = None;
-	for (mv, child) in bs.iter() {
-		let score = maximize(child, alpha, beta, depth + 1, opts).0;
-		if score < best_score {
-			best_score = score;
-			if depth == 0 {
-				best_move = Some(*mv);
+		let mut best_score: Value = Value::MAX - 1;
+		let mut pv = Vec::new();
+		for (mv, child) in bs.iter() {
+			let (score, child_pv) = self.maximize(child, alpha, beta, depth + 1);
+			if self.state.stopped() {
+				break;
 			}
-			if opts.alpha_beta {
-				beta = cmp::min(beta, best_score);
-				if beta <= alpha {
-					break;
+			if score < best_score {
+				best_score = score;
+				pv = vec![*mv];
+				pv.extend(child_pv);
+				if self.state.opts.alpha_beta {
+					beta = cmp::min(beta, best_score);
+					if beta <= alpha {
+						break;
+					}
+				}
+			}
+		}
+		(best_score, pv)
+	}
+}
+
+impl Searcher for Minimax {
+	fn state_mut(&mut self) -> &mut SearchState {
+		&mut self.state
+	}
+
+	/// Iterative deepening, so that an interrupted search returns the result
+	/// of the last completed iteration
+	fn search_root(&mut self, b: &Board) -> Option<SearchResult> {
+		let mut best: Option<(Value, Vec<Move>)> = None;
+		for depth in 1..=self.state.limits.max_depth {
+			self.horizon = depth;
+			let (v, pv) = match b.player {
+				Color::Black => self.minimize(b, Value::MIN, Value::MAX, 0),
+				Color::White => self.maximize(b, Value::MIN, Value::MAX, 0),
+			};
+			if self.state.stopped() {
+				if best.is_none() {
+					best = Some((v, pv));
 				}
+				break;
 			}
+			self.state.info.depth = depth;
+			self.state.info.set_score(if b.player == Color::White { v } else { -v });
+			best = Some((v, pv));
 		}
+		let (v, pv) = best?;
+		self.state.result(b, v, pv)
 	}
-	(best_score, best_move)
 }
@@ -1,6 +1,14 @@
 use std::str::FromStr;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 use crate::board::{Move};
-use crate::evaluation::{Value};
+use crate::book::Book;
+use crate::syzygy::Tablebases;
+use crate::evaluation::{Value, mate_distance};
+use crate::nnue::Network;
+use crate::params::{EvalParams, PARAMS_DEFAULT};
+use crate::tt::DEFAULT_TT_SIZE_MB;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SearchAlgorithm {
@@ -41,6 +49,29 @@ impl FromStr for MoveOrdering {
 	}
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Evaluation {
+	/// Material, tapered piece-square tables, pawn structure and positional terms
+	#[default]
+	Classic,
+	/// Material and tapered piece-square tables only
+	Simplified,
+	/// Neural network, see `Options::nnue`
+	Nnue,
+}
+impl FromStr for Evaluation {
+	type Err = ();
+
+	fn from_str(input: &str) -> Result<Evaluation, Self::Err> {
+		match input.to_lowercase().as_str() {
+			"classic" => Ok(Evaluation::Classic),
+			"simplified" => Ok(Evaluation::Simplified),
+			"nnue" => Ok(Evaluation::Nnue),
+			_ => Err(()),
+		}
+	}
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Ui {
 	Uci,
@@ -58,21 +89,66 @@ impl FromStr for Ui {
 	}
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Options {
 	pub search_algo: SearchAlgorithm,
 	pub move_ordering: MoveOrdering,
+	pub eval: Evaluation,
+	/// Evaluation parameters, the built-in ones if not set
+	pub params: Option<Arc<EvalParams>>,
+	/// Network of the `Nnue` evaluation
+	pub nnue: Option<Arc<Network>>,
 	pub max_depth: u8,
 	pub ui: Ui,
 	pub alpha_beta: bool,
+	pub null_move_pruning: bool,
+	pub late_move_reductions: bool,
+	pub aspiration: bool,
+	/// Number of search threads (negascout only)
+	pub threads: usize,
+	/// Size of the transposition table, in megabytes
+	pub hash_mb: usize,
+	/// Number of best root moves to report
+	pub multi_pv: usize,
+	/// Whether the GUI lets the engine think on the opponent's time
+	pub ponder: bool,
+	/// Opening book
+	pub book: Option<Arc<Book>>,
+	/// Whether to play moves from the book while there are any
+	pub own_book: bool,
+	/// Book moves picked at random by weight, rather than the one with the highest weight
+	pub book_random: bool,
+	/// Endgame tablebases, probed at the root and in the search
+	pub syzygy: Option<Arc<Tablebases>>,
+}
+
+impl Options {
+	/// The evaluation parameters in use
+	pub fn eval_params(&self) -> &EvalParams {
+		self.params.as_deref().unwrap_or(&PARAMS_DEFAULT)
+	}
 }
 
 pub static OPTS_DEFAULT: Options = Options {
 	search_algo: SearchAlgorithm::Negamax,
 	move_ordering: MoveOrdering::Eval,
+	eval: Evaluation::Classic,
+	params: None,
+	nnue: None,
 	max_depth: 5,
 	ui: Ui::Uci,
 	alpha_beta: true,
+	null_move_pruning: true,
+	late_move_reductions: true,
+	aspiration: true,
+	threads: 1,
+	hash_mb: DEFAULT_TT_SIZE_MB,
+	multi_pv: 1,
+	ponder: false,
+	book: None,
+	own_book: false,
+	book_random: true,
+	syzygy: None,
 };
 
 #[derive(Default,Debug, Copy, Clone, PartialEq, Eq)]
@@ -80,13 +156,89 @@ pub struct SearchInfo {
 	pub depth: u8,
 	pub curr_move: Option<Move>,
 	pub score_cp: Value,
+	pub score_mate: Option<i8>,
 	pub nodes: u64,
-	pub nps: u64
+	pub nps: u64,
+	/// Number of aspiration window re-searches
+	pub researches: u32,
 }
 impl SearchInfo {
 	pub fn new() -> Self {
 			Default::default()
 	}
+
+	/// Records a score from the point of view of the side to move,
+	/// turning mate scores into a distance to mate
+	pub fn set_score(&mut self, score: Value) {
+		self.score_cp = score;
+		self.score_mate = mate_distance(score);
+	}
+}
+
+/// A candidate line found by the search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PvLine {
+	/// Score of the line, from White's point of view
+	pub score: Value,
+	pub pv: Vec<Move>,
+}
+
+/// Outcome of a search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+	/// Score of the position, from White's point of view
+	pub score: Value,
+	pub best_move: Move,
+	/// Principal variation, starting with `best_move`
+	pub pv: Vec<Move>,
+	/// Depth of the last completed search
+	pub depth: u8,
+	pub nodes: u64,
+	/// Time spent searching, in milliseconds
+	pub time_ms: u64,
+	/// Number of aspiration window re-searches
+	pub researches: u32,
+	/// The best root moves with their lines, best first.
+	/// Holds `Options::multi_pv` lines at most, the first one being `pv`
+	pub lines: Vec<PvLine>,
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+	#[wasm_bindgen(js_namespace = Date, js_name = now)]
+	fn date_now() -> f64;
+}
+
+/// Measures elapsed time. `std::time::Instant` is not available in the browser,
+/// so the wasm build falls back to `Date.now()`
+#[derive(Debug, Copy, Clone)]
+pub struct Stopwatch {
+	#[cfg(not(target_arch = "wasm32"))]
+	start: Instant,
+	#[cfg(target_arch = "wasm32")]
+	start: f64,
+}
+impl Stopwatch {
+	#[cfg(not(target_arch = "wasm32"))]
+	pub fn start() -> Self {
+		Stopwatch { start: Instant::now() }
+	}
+
+	#[cfg(target_arch = "wasm32")]
+	pub fn start() -> Self {
+		Stopwatch { start: date_now() }
+	}
+
+	#[cfg(not(target_arch = "wasm32"))]
+	pub fn elapsed_ms(&self) -> u64 {
+		self.start.elapsed().as_millis() as u64
+	}
+
+	#[cfg(target_arch = "wasm32")]
+	pub fn elapsed_ms(&self) -> u64 {
+		(date_now() - self.start) as u64
+	}
 }
 
 #[allow(dead_code,non_snake_case)]
@@ -1,65 +1,100 @@
 use std::cmp;
 
 use crate::board::{Board, Color, Move};
-use crate::evaluation::{Valuable, Value};
+use crate::evaluation::{Value, mated_in};
 use crate::misc::*;
 use crate::ordering::move_ordering;
+use crate::search::{Searcher, SearchState};
 
-pub fn negamax(
-	b: & Board,
-	opts: &Options,
-) -> Option<(Value, Move)> {
-	let mut si = SearchInfo::new();
+pub struct Negamax {
+	state: SearchState,
+	/* Depth of the current iteration */
+	horizon: u8,
+}
+
+impl Negamax {
+	pub fn new(opts: &Options) -> Self {
+		Negamax { state: SearchState::new(opts), horizon: 0 }
+	}
+
+	fn negamax_search(
+		&mut self,
+		b: &Board,
+		mut alpha: Value,
+		beta: Value,
+		depth: u8,
+		sign: i8,
+	) -> (Value, Vec<Move>) {
+		if self.state.visit() {
+			return (0, Vec::new());
+		}
+		if depth == self.horizon {
+			return (sign as Value * self.state.eval.evaluate(b), Vec::new());
+		}
+
+		let mut bs: Vec<(Move, Board)> = b.generate_all();
 
-	match b.player {
-		Color::Black => {
-			if let (v, Some(mv)) = negamax_search(b, Value::MIN + 1, Value::MAX - 1, 0, -1, &mut si, opts) {
-				return Some((-v, mv));
+		if bs.is_empty() {
+			/* Checkmate or stalemate */
+			return (if b.is_king_in_check(b.player) { mated_in(depth) } else { 0 }, Vec::new());
+		}
+
+		move_ordering(&mut bs, sign, &self.state.opts, self.state.eval.as_ref());
+		if depth == 0 {
+			self.state.exclude_root_moves(&mut bs);
+		}
+
+		let mut best_score: Value = Value::MIN + 1;
+		let mut pv = Vec::new();
+		for (mv, child) in bs.iter() {
+			let (score, child_pv) = self.negamax_search(child, -beta, -alpha, depth + 1, -sign);
+			if self.state.stopped() {
+				break;
 			}
-		},
-		Color::White => {
-			if let (v, Some(mv)) = negamax_search(b, Value::MIN + 1, Value::MAX - 1, 0, 1, &mut si, opts) {
-				return Some((v, mv));
+			let score = -score;
+			if score > best_score {
+				best_score = score;
+				pv = vec![*mv];
+				pv.extend(child_pv);
+				if self.state.opts.alpha_beta {
+					alpha = cmp::max(alpha, best_score);
+					if alpha >= beta {
+						break;
+					}
+				}
 			}
 		}
+		(best_score, pv)
 	}
-	None
 }
 
-fn negamax_search(
-	b: &Board,
-	mut alpha: Value,
-	beta: Value,
-	depth: u8,
-	sign: i8,
-	si: &mut SearchInfo,
-	opts: &Options,
-) -> (Value, Option<Move>) {
-	if depth == opts.max_depth {
-		return (sign as Value * b.value(), None);
+impl Searcher for Negamax {
+	fn state_mut(&mut self) -> &mut SearchState {
+		&mut self.state
 	}
-	si.nodes = si.nodes + 1;
-	
-	let mut bs: Vec<(Move, Board)> = b.generate_all();
-	
-	move_ordering(&mut bs, sign, opts);
 
-	let mut best_score: Value = Value::MIN + 1;
-	let mut best_move = None;
-	for (mv, child) in bs.iter() {
-		let score = -negamax_search(child, -beta, -alpha, depth + 1, -sign, si, opts).0;
-		if score > best_score {
-			best_score = score;
-			if depth == 0 {
-				best_move = Some(*mv);
-			}
-			if opts.alpha_beta {
-				alpha = cmp::max(alpha, best_score);
-				if alpha >= beta {
-					break;
+	/// Iterative deepening, so that an interrupted search returns the result
+	/// of the last completed iteration
+	fn search_root(&mut self, b: &Board) -> Option<SearchResult> {
+		let sign: i8 = match b.player {
+			Color::Black => -1,
+			Color::White => 1,
+		};
+		let mut best: Option<(Value, Vec<Move>)> = None;
+		for depth in 1..=self.state.limits.max_depth {
+			self.horizon = depth;
+			let (v, pv) = self.negamax_search(b, Value::MIN + 1, Value::MAX - 1, 0, sign);
+			if self.state.stopped() {
+				if best.is_none() {
+					best = Some((v, pv));
 				}
+				break;
 			}
+			self.state.info.depth = depth;
+			self.state.info.set_score(v);
+			best = Some((v, pv));
 		}
+		let (v, pv) = best?;
+		self.state.result(b, sign as Value * v, pv)
 	}
-	(best_score, best_move)
 }
@@ -1,65 +1,437 @@
 use std::cmp;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::Ordering;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::{self, JoinHandle};
 
-use crate::board::{Board, Color, Move};
-use crate::evaluation::{Valuable, Value};
+use crate::board::{Board, Color, Move, Piece};
+use crate::evaluation::{Value, is_mate_score, mated_in};
 use crate::misc::*;
 use crate::ordering::move_ordering;
+use crate::search::{Searcher, SearchState};
+use crate::syzygy::{Wdl, piece_count};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::search::SearchControl;
+use crate::tt::{Bound, TTEntry, score_from_tt, score_to_tt};
 
-pub fn negascout(
-	b: &Board,
-	opts: &Options,
-) -> Option<(Value, Move)> {
-	match b.player {
-		Color::Black => {
-			if let (v, Some(mv)) = negascout_search(b, Value::MIN + 1, Value::MAX - 1, 0, -1, opts) {
-				return Some((-v, mv));
+/* Depth reduction applied to the null-move search */
+const NULL_MOVE_R: u8 = 2;
+/* Late move reductions only kick in from this remaining depth on */
+const LMR_MIN_DEPTH: u8 = 3;
+/* Number of moves searched at full depth before reductions are considered */
+const LMR_FULL_MOVES: usize = 3;
+/* Half-width of the initial aspiration window */
+const ASPIRATION_WINDOW: Value = 50;
+/* Iterations shallower than this are searched with a full window */
+const ASPIRATION_MIN_DEPTH: u8 = 3;
+/* The largest tablebases are only probed with at least this remaining depth */
+const SYZYGY_PROBE_DEPTH: u8 = 2;
+
+pub struct Negascout {
+	state: SearchState,
+	/* Best root move of the previous iteration, searched first */
+	pv_move: Option<Move>,
+}
+
+impl Negascout {
+	pub fn new(opts: &Options) -> Self {
+		Negascout { state: SearchState::new(opts), pv_move: None }
+	}
+
+	// Implementation inspired to https://homepage.iis.sinica.edu.tw/~tshsu/tcg/2018/slides/slide7.pdf
+	// `ply` is the distance from the root, `depth` the remaining depth to the horizon.
+	// `zeroing` tells that the position was reached by a capture or a pawn move.
+	#[allow(clippy::too_many_arguments)]
+	fn negascout_search(
+		&mut self,
+		b: &Board,
+		alpha: Value,
+		beta: Value,
+		ply: u8,
+		depth: u8,
+		sign: i8,
+		allow_null: bool,
+		zeroing: bool,
+	) -> (Value, Vec<Move>) {
+		if self.state.visit() {
+			return (0, Vec::new());
+		}
+		if depth == 0 {
+			return (sign as Value * self.state.eval.evaluate(b), Vec::new());
+		}
+
+		/* Transposition table: reuse the result of an earlier search of this position,
+		 * or at least try its best move first */
+		let hash = b.hash();
+		let mut tt_move = None;
+		if let Some(entry) = self.state.tt.probe(hash) {
+			tt_move = entry.mv;
+			if ply > 0 && entry.depth >= depth {
+				let score = score_from_tt(entry.score, ply);
+				let cutoff = match entry.bound {
+					Bound::Exact => true,
+					Bound::Lower => score >= beta,
+					Bound::Upper => score <= alpha,
+				};
+				if cutoff {
+					return (score, tt_move.into_iter().collect());
+				}
+			}
+		}
+
+		/* Endgame tablebases: the exact result, once few enough pieces are left.
+		 * Probing is costly, so as in the reference engines it is only done right after
+		 * a capture or a pawn move, the only moves that can bring the position into the tables,
+		 * and the largest tables only when the remaining depth is worth it */
+		if ply > 0 && zeroing {
+			if let Some(tb) = self.state.opts.syzygy.clone() {
+				let pieces = piece_count(b);
+				if pieces < tb.max_pieces() || (pieces == tb.max_pieces() && depth >= SYZYGY_PROBE_DEPTH) {
+					if let Some(wdl) = tb.probe_wdl(b) {
+						let score = wdl.value(ply);
+						let bound = match wdl {
+							Wdl::Win | Wdl::CursedWin => Bound::Lower,
+							Wdl::Draw => Bound::Exact,
+							Wdl::BlessedLoss | Wdl::Loss => Bound::Upper,
+						};
+						self.state.tt.store(hash, TTEntry { score: score_to_tt(score, ply), depth: u8::MAX, bound, mv: None });
+						return (score, Vec::new());
+					}
+				}
 			}
 		}
-		Color::White => {
-			if let (v, Some(mv)) = negascout_search(b, Value::MIN + 1, Value::MAX - 1, 0, 1, opts) {
-				return Some((v, mv));
+
+		let in_check = b.is_king_in_check(b.player);
+
+		/* Null-move pruning: give the opponent a free move.
+		 * If we still fail high, the position is good enough to cut */
+		if self.state.opts.null_move_pruning && allow_null && ply > 0 && depth > NULL_MOVE_R
+				&& null_move_allowed(b, in_check) {
+			let null_b = b.clone_null_move();
+			let score = -self.negascout_search(&null_b, -beta, -beta + 1, ply + 1, depth - 1 - NULL_MOVE_R, -sign, false, false).0;
+			if self.state.stopped() {
+				return (0, Vec::new());
+			}
+			if score >= beta {
+				return (beta, Vec::new());
 			}
 		}
-	}
-	None
-}
 
-// Implementation inspired to https://homepage.iis.sinica.edu.tw/~tshsu/tcg/2018/slides/slide7.pdf
-fn negascout_search(
-	b: &Board,
-	alpha: Value,
-	beta: Value,
-	depth: u8,
-	sign: i8,
-	opts: &Options,
-) -> (Value, Option<Move>) {
-	if depth == opts.max_depth {
-		return (sign as Value * b.value(), None);
+		let mut bs: Vec<(Move, Board)> = b.generate_all();
+
+		if bs.is_empty() {
+			/* Checkmate or stalemate */
+			return (if in_check { mated_in(ply) } else { 0 }, Vec::new());
+		}
+
+		move_ordering(&mut bs, sign, &self.state.opts, self.state.eval.as_ref());
+		if ply == 0 {
+			self.state.exclude_root_moves(&mut bs);
+		}
+		let first_move = if ply == 0 && self.pv_move.is_some() { self.pv_move } else { tt_move };
+		if let Some(idx) = bs.iter().position(|(mv, _)| Some(*mv) == first_move) {
+			let first = bs.remove(idx);
+			bs.insert(0, first);
+		}
+
+		let mut lower: Value = Value::MIN + 1;
+		let mut upper: Value = beta;
+		let mut pv = Vec::new();
+		for (i, (mv, child)) in bs.iter().enumerate() {
+			let zeroing = b.at(mv.t_pos).is_some() || b.at(mv.f_pos).is_some_and(|t| t.piece == Piece::Pawn);
+			/* Late move reductions: quiet moves ordered late are first searched at reduced depth,
+			 * and only re-searched at full depth if they turn out to improve alpha */
+			if self.state.opts.late_move_reductions && ply > 0 && depth >= LMR_MIN_DEPTH && i >= LMR_FULL_MOVES
+					&& !in_check && b.is_quiet_move(mv) && !child.is_king_in_check(child.player) {
+				let r = late_move_reduction(depth, i);
+				let score = -self.negascout_search(child, -upper, -cmp::max(alpha,lower), ply + 1, depth - 1 - r, -sign, true, zeroing).0;
+				if self.state.stopped() {
+					break;
+				}
+				if score <= cmp::max(alpha,lower) {
+					continue;
+				}
+			}
+			let (score, child_pv) = self.negascout_search(child, -upper, -cmp::max(alpha,lower), ply + 1, depth - 1, -sign, true, zeroing);
+			if self.state.stopped() {
+				break;
+			}
+			let score = -score;
+			if score > lower {
+				let mut child_pv = child_pv;
+				if upper == beta || depth < 3 || score >= beta {
+					lower = score;
+				} else {
+					let (score, research_pv) = self.negascout_search(child, -beta, -score, ply + 1, depth - 1, -sign, true, zeroing);
+					if self.state.stopped() {
+						break;
+					}
+					lower = -score;
+					child_pv = research_pv;
+				}
+				pv = vec![*mv];
+				pv.extend(child_pv);
+			}
+			if lower >= beta {
+				break;
+			}
+			upper = cmp::max(alpha,lower) + 1;
+		}
+		// with excluded root moves the root score is not the score of the position
+		if !self.state.stopped() && (ply > 0 || self.state.excluded.is_empty()) {
+			let bound = if lower >= beta {
+				Bound::Lower
+			} else if lower > alpha {
+				Bound::Exact
+			} else {
+				Bound::Upper
+			};
+			self.state.tt.store(hash, TTEntry { score: score_to_tt(lower, ply), depth, bound, mv: pv.first().copied() });
+		}
+		(lower, pv)
 	}
-	
-	let mut bs: Vec<(Move, Board)> = b.generate_all();
-
-	move_ordering(&mut bs, sign, opts);
-
-	let mut lower: Value = Value::MIN + 1;
-	let mut upper: Value = beta;
-	let mut best_move = None;
-	for (mv, child) in bs.iter() {
-		let score = -negascout_search(child, -upper, -cmp::max(alpha,lower), depth + 1, -sign, opts).0;
-		if score > lower {
-			if depth == 0 {
-				best_move = Some(*mv);
+
+	/// Root search within a narrow window around the score of the previous iteration.
+	/// The window is widened and the search repeated whenever the score falls outside of it.
+	fn aspiration_search(&mut self, b: &Board, depth: u8, sign: i8, prev_score: Option<Value>) -> (Value, Vec<Move>) {
+		let (mut alpha, mut beta) = match prev_score {
+			Some(v) if self.state.opts.aspiration && depth >= ASPIRATION_MIN_DEPTH && !is_mate_score(v) =>
+				(v.saturating_sub(ASPIRATION_WINDOW).max(Value::MIN + 1), v.saturating_add(ASPIRATION_WINDOW).min(Value::MAX - 1)),
+			_ => (Value::MIN + 1, Value::MAX - 1),
+		};
+		let mut delta = ASPIRATION_WINDOW;
+		loop {
+			let (v, pv) = self.negascout_search(b, alpha, beta, 0, depth, sign, true, false);
+			if self.state.stopped() {
+				return (v, pv);
 			}
-			if upper == beta || depth > (opts.max_depth - 3) || score >= beta {
-				lower = score;
+			if v <= alpha && alpha > Value::MIN + 1 {
+				/* fail-low */
+				alpha = alpha.saturating_sub(delta).max(Value::MIN + 1);
+			} else if v >= beta && beta < Value::MAX - 1 {
+				/* fail-high */
+				beta = beta.saturating_add(delta).min(Value::MAX - 1);
 			} else {
-				lower = -negascout_search(child, -beta, -score, depth + 1, -sign, opts).0;
+				return (v, pv);
+			}
+			delta = delta.saturating_mul(2);
+			self.state.info.researches += 1;
+		}
+	}
+
+	/// Iterative deepening: each iteration searches one ply deeper, until the
+	/// depth limit is reached or the search is stopped. The result of the last
+	/// completed iteration is returned.
+	fn iterative_deepening(&mut self, b: &Board, first_depth: u8) -> Option<(Value, Vec<Move>)> {
+		let sign: i8 = match b.player {
+			Color::Black => -1,
+			Color::White => 1,
+		};
+		let mut best: Option<(Value, Vec<Move>)> = None;
+		for depth in first_depth..=self.state.limits.max_depth {
+			let prev_score = best.as_ref().map(|(v, _)| *v);
+			let (v, pv) = self.aspiration_search(b, depth, sign, prev_score);
+			if self.state.stopped() {
+				if best.is_none() {
+					best = Some((v, pv));
+				}
+				break;
 			}
+			self.state.info.depth = depth;
+			self.state.info.set_score(v);
+			self.pv_move = pv.first().copied();
+			best = Some((v, pv));
 		}
-		if lower >= beta {
-			break;
+		best
+	}
+
+	/// Lazy SMP: helper threads search the same root, sharing the transposition table
+	/// with the main thread. Odd helpers start one ply deeper, so that threads
+	/// diverge and fill the table with useful entries for each other.
+	#[cfg(not(target_arch = "wasm32"))]
+	fn spawn_helpers(&self, b: &Board) -> (Arc<SearchControl>, Vec<JoinHandle<u64>>) {
+		let stop = Arc::new(SearchControl::default());
+		let handles = (1..self.state.opts.threads).map(|id| {
+			let mut helper = Negascout { state: self.state.helper(stop.clone()), pv_move: None };
+			let b = b.clone();
+			thread::spawn(move || {
+				helper.state.start();
+				helper.iterative_deepening(&b, 1 + (id % 2) as u8);
+				helper.state.info.nodes
+			})
+		}).collect();
+		(stop, handles)
+	}
+}
+
+impl Searcher for Negascout {
+	fn state_mut(&mut self) -> &mut SearchState {
+		&mut self.state
+	}
+
+	fn search_root(&mut self, b: &Board) -> Option<SearchResult> {
+		self.pv_move = None;
+
+		#[cfg(not(target_arch = "wasm32"))]
+		let (helpers_stop, helpers) = self.spawn_helpers(b);
+
+		let best = self.iterative_deepening(b, 1);
+
+		#[cfg(not(target_arch = "wasm32"))]
+		{
+			helpers_stop.abort.store(true, Ordering::Relaxed);
+			for h in helpers {
+				self.state.info.nodes += h.join().unwrap_or(0);
+			}
+		}
+
+		let (v, pv) = best?;
+		let sign: Value = if b.player == Color::White { 1 } else { -1 };
+		self.state.result(b, sign * v, pv)
+	}
+}
+
+/// Whether passing is sound: not in check, where it would be illegal,
+/// nor when only pawns are left, where zugzwang is common
+fn null_move_allowed(b: &Board, in_check: bool) -> bool {
+	!in_check && b.has_non_pawn_material(b.player)
+}
+
+/// How many plies to take off a late quiet move
+fn late_move_reduction(depth: u8, move_idx: usize) -> u8 {
+	let r = if move_idx >= 2 * LMR_FULL_MOVES { 2 } else { 1 };
+	// always leave at least one ply to search
+	cmp::min(r, depth - 2)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use std::sync::atomic::Ordering;
+	use std::thread;
+	use std::time::{Duration, Instant};
+	use crate::board::{Board,Color,Move};
+	use crate::evaluation::{MATE,mate_distance};
+	use crate::misc::OPTS_DEFAULT;
+	use crate::negascout::{Negascout,null_move_allowed};
+	use crate::search::Searcher;
+	use crate::syzygy::{TB_WIN, Tablebases};
+	use crate::tt::Bound;
+
+	#[test]
+	pub fn test_mate_in_one() {
+		let b = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 0").unwrap();
+		let mut opts = OPTS_DEFAULT.clone();
+		opts.max_depth = 3;
+		let res = Negascout::new(&opts).search(&b).expect("a move");
+		debug_assert_eq!(res.best_move, "a1a8".parse::<Move>().unwrap());
+		debug_assert_eq!(res.pv, vec![res.best_move]);
+		debug_assert_eq!(res.score, MATE - 1);
+		debug_assert_eq!(mate_distance(res.score), Some(1));
+	}
+
+	#[test]
+	pub fn test_threads() {
+		let b = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 0").unwrap();
+		let mut opts = OPTS_DEFAULT.clone();
+		opts.max_depth = 4;
+		let single = Negascout::new(&opts).search(&b).expect("a move");
+		opts.threads = 4;
+		// the helpers are joined before the result comes back
+		let res = Negascout::new(&opts).search(&b).expect("a move");
+		debug_assert_eq!((res.best_move, res.score), (single.best_move, single.score));
+
+		// an endless search, stopped from outside: the helpers stop with it
+		opts.max_depth = 64;
+		let mut searcher = Negascout::new(&opts);
+		let control = searcher.state.control.clone();
+		let stopper = thread::spawn(move || {
+			thread::sleep(Duration::from_millis(100));
+			control.abort.store(true, Ordering::Relaxed);
+		});
+		let start = Instant::now();
+		let res = searcher.search(&Board::new(Color::White)).expect("a move");
+		stopper.join().unwrap();
+		debug_assert!(start.elapsed() < Duration::from_secs(10));
+		debug_assert!(res.depth < 64);
+	}
+
+	#[test]
+	pub fn test_stalemate() {
+		let b = Board::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 0").unwrap();
+		debug_assert!(Negascout::new(&OPTS_DEFAULT).search(&b).is_none());
+	}
+
+	#[test]
+	pub fn test_pruning() {
+		// Ne2+ forks the king and the queen
+		let b = Board::from_fen("6k1/5ppp/8/8/3n4/2Q5/5PPP/6K1 b - - 0 1").unwrap();
+		let search = |null_move: bool, lmr: bool| {
+			let mut opts = OPTS_DEFAULT.clone();
+			opts.max_depth = 5;
+			opts.null_move_pruning = null_move;
+			opts.late_move_reductions = lmr;
+			Negascout::new(&opts).search(&b).expect("a move")
+		};
+		let full = search(false, false);
+		debug_assert_eq!(full.best_move, "d4e2".parse::<Move>().unwrap());
+		for (null_move, lmr) in [(true, false), (false, true), (true, true)] {
+			let res = search(null_move, lmr);
+			debug_assert_eq!(res.best_move, full.best_move);
+			debug_assert!(res.nodes < full.nodes);
+		}
+	}
+
+	#[test]
+	pub fn test_syzygy() {
+		// Rxc7 leaves KRvK, which the tables know to be won
+		let b = Board::from_fen("8/2n5/8/8/8/3k4/2R5/1K6 w - - 0 1").unwrap();
+		let mut opts = OPTS_DEFAULT.clone();
+		opts.max_depth = 3;
+		opts.syzygy = Some(Arc::new(Tablebases::open(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/syzygy")).unwrap()));
+		let mut searcher = Negascout::new(&opts);
+		let res = searcher.search(&b).expect("a move");
+		debug_assert_eq!(res.best_move, "c2c7".parse::<Move>().unwrap());
+		debug_assert_eq!(res.score, TB_WIN - 1);
+		// the result is kept in the transposition table, as a bound for the losing side
+		let child = b.clone_apply_move(&res.best_move);
+		let entry = searcher.state.tt.probe(child.hash()).expect("a table entry");
+		debug_assert_eq!((entry.score, entry.bound), (-TB_WIN, Bound::Upper));
+	}
+
+	#[test]
+	pub fn test_null_move_allowed() {
+		let b = Board::from_fen("6k1/5ppp/8/8/3n4/2Q5/5PPP/6K1 b - - 0 1").unwrap();
+		debug_assert!(null_move_allowed(&b, false));
+		// in check
+		let b = Board::from_fen("6k1/5ppp/8/8/8/2Q5/4nPPP/6K1 w - - 0 1").unwrap();
+		debug_assert!(!null_move_allowed(&b, b.is_king_in_check(b.player)));
+		// only pawns left
+		let b = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1").unwrap();
+		debug_assert!(!null_move_allowed(&b, false));
+	}
+
+	#[test]
+	pub fn test_aspiration() {
+		let b = Board::from_fen("6k1/5ppp/8/8/3n4/2Q5/5PPP/6K1 b - - 0 1").unwrap();
+		let mut opts = OPTS_DEFAULT.clone();
+		opts.max_depth = 4;
+		let aspiration = |prev_score: Option<i16>| {
+			let mut searcher = Negascout::new(&opts);
+			searcher.state.start();
+			let (v, pv) = searcher.aspiration_search(&b, 4, -1, prev_score);
+			(v, pv[0], searcher.state.info.researches)
+		};
+		let (v, mv, researches) = aspiration(None);
+		debug_assert_eq!(researches, 0);
+		debug_assert_eq!(aspiration(Some(v)), (v, mv, 0));
+		// fail high, then fail low: the window is widened until it holds the score
+		for prev_score in [v - 300, v + 300] {
+			let (v2, mv2, researches) = aspiration(Some(prev_score));
+			debug_assert_eq!((v2, mv2), (v, mv));
+			debug_assert!(researches >= 2);
 		}
-		upper = cmp::max(alpha,lower) + 1;
 	}
-	(lower, best_move)
 }
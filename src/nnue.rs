@@ -0,0 +1,255 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::sync::Arc;
+
+use crate::board::{Board, Color, Pos, Tile};
+use crate::evaluation::{Evaluator, Value, MATE, MAX_PLY};
+use crate::misc::Evaluation;
+use crate::trace::EvalTrace;
+
+/*
+ * Efficiently updatable neural network: https://www.chessprogramming.org/NNUE
+ * Inputs are the 768 (color, piece, square) features of the board, seen from each side.
+ * Each side has an accumulator with the first layer outputs, updated incrementally
+ * as pieces are added and removed. The two accumulators, side to move first, go through
+ * a clipped ReLU into a single output neuron.
+ * Plain integer arithmetic only, no SIMD required.
+ *
+ * Weights file, all little-endian:
+ *   "DNN1", hidden size H (u32, at most 256),
+ *   feature weights (768 x H i16, feature-major), feature biases (H i16),
+ *   output weights (2H i16, side to move first), output bias (i32)
+*/
+
+const MAGIC: &[u8; 4] = b"DNN1";
+const FEATURES: usize = 2 * 6 * 64;
+/* The accumulators are arrays copied with the board, not heap allocations, so the hidden layer stays small */
+const MAX_HIDDEN: usize = 256;
+/* Quantization of the hidden layer (clipped ReLU range) and of the output weights */
+const QA: i32 = 255;
+const QB: i32 = 64;
+/* Output scale, in centipawns */
+const SCALE: i32 = 400;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+	hidden: usize,
+	feature_weights: Vec<i16>,
+	feature_bias: Vec<i16>,
+	output_weights: Vec<i16>,
+	output_bias: i32,
+}
+
+impl Network {
+	pub fn from_bytes(bytes: &[u8]) -> Result<Network, String> {
+		if bytes.len() < 8 || &bytes[..4] != MAGIC {
+			return Err(String::from("not a network file"));
+		}
+		let hidden = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+		if hidden == 0 || hidden > MAX_HIDDEN {
+			return Err(format!("unsupported hidden layer size: {}", hidden));
+		}
+		let expected = 8 + 2 * (FEATURES * hidden + hidden + 2 * hidden) + 4;
+		if bytes.len() != expected {
+			return Err(format!("expected {} bytes, found {}", expected, bytes.len()));
+		}
+		let mut rest = &bytes[8..];
+		let mut read_i16 = |n: usize| -> Vec<i16> {
+			let (data, tail) = rest.split_at(2 * n);
+			rest = tail;
+			data.chunks(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect()
+		};
+		let feature_weights = read_i16(FEATURES * hidden);
+		let feature_bias = read_i16(hidden);
+		let output_weights = read_i16(2 * hidden);
+		let output_bias = i32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+		Ok(Network { hidden, feature_weights, feature_bias, output_weights, output_bias })
+	}
+
+	/// Writes the network in the format read by `from_bytes`
+	#[allow(dead_code)]
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = MAGIC.to_vec();
+		bytes.extend((self.hidden as u32).to_le_bytes());
+		for w in self.feature_weights.iter().chain(&self.feature_bias).chain(&self.output_weights) {
+			bytes.extend(w.to_le_bytes());
+		}
+		bytes.extend(self.output_bias.to_le_bytes());
+		bytes
+	}
+
+	#[cfg(not(target_arch = "wasm32"))]
+	#[allow(dead_code)]
+	pub fn load(path: &str) -> Result<Network, String> {
+		let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+		Network::from_bytes(&bytes).map_err(|e| format!("{}: {}", path, e))
+	}
+
+	/// Output for the side to move, in centipawns
+	fn output(&self, acc: &Accumulator, stm: Color) -> i32 {
+		let (us, them) = (&acc.values[stm as usize][..self.hidden], &acc.values[stm.swap() as usize][..self.hidden]);
+		let (w_us, w_them) = self.output_weights.split_at(self.hidden);
+		let crelu = |v: i16| (v as i32).clamp(0, QA);
+		let mut sum = 0i64;
+		for i in 0..self.hidden {
+			sum += (crelu(us[i]) * w_us[i] as i32) as i64;
+			sum += (crelu(them[i]) * w_them[i] as i32) as i64;
+		}
+		((sum + self.output_bias as i64) * SCALE as i64 / (QA * QB) as i64) as i32
+	}
+}
+
+/// Input feature of a piece on a square, as seen from `perspective`:
+/// boards are flipped vertically for Black, so that each side sees its own pieces as "ours"
+#[inline]
+fn feature(perspective: Color, tile: Tile, pos: Pos) -> usize {
+	let (side, row) = match perspective {
+		Color::White => (if tile.color == Color::White { 0 } else { 1 }, pos.row),
+		Color::Black => (if tile.color == Color::Black { 0 } else { 1 }, 7 - pos.row),
+	};
+	(side * 6 + tile.piece as usize) * 64 + (row * 8 + pos.col) as usize
+}
+
+/// First layer outputs for both perspectives, kept in sync with the board by `make_move`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accumulator {
+	net: Arc<Network>,
+	/// By perspective color, only the first `net.hidden` values are used
+	values: [[i16; MAX_HIDDEN]; 2],
+}
+
+impl Accumulator {
+	/// Computes the accumulators of the board from scratch
+	pub fn new(net: Arc<Network>, b: &Board) -> Self {
+		let mut values = [[0; MAX_HIDDEN]; 2];
+		for v in values.iter_mut() {
+			v[..net.hidden].copy_from_slice(&net.feature_bias);
+		}
+		let mut acc = Accumulator { values, net };
+		for c in 0..8 {
+			for r in 0..8 {
+				let pos = Pos::at(c, r).unwrap();
+				if let Some(tile) = b.at(pos) {
+					acc.add(*tile, pos);
+				}
+			}
+		}
+		acc
+	}
+
+	fn update(&mut self, tile: Tile, pos: Pos, add: bool) {
+		let hidden = self.net.hidden;
+		for perspective in [Color::White, Color::Black] {
+			let f = feature(perspective, tile, pos);
+			let weights = &self.net.feature_weights[f * hidden..(f + 1) * hidden];
+			for (v, w) in self.values[perspective as usize][..hidden].iter_mut().zip(weights) {
+				*v = if add { v.wrapping_add(*w) } else { v.wrapping_sub(*w) };
+			}
+		}
+	}
+
+	#[inline]
+	pub fn add(&mut self, tile: Tile, pos: Pos) {
+		self.update(tile, pos, true);
+	}
+
+	#[inline]
+	pub fn remove(&mut self, tile: Tile, pos: Pos) {
+		self.update(tile, pos, false);
+	}
+}
+
+/// Evaluation by a neural network
+#[derive(Debug)]
+pub struct Nnue {
+	net: Arc<Network>,
+}
+
+impl Nnue {
+	pub fn new(net: Arc<Network>) -> Self {
+		Nnue { net }
+	}
+}
+
+impl Evaluator for Nnue {
+	fn evaluate(&self, b: &Board) -> Value {
+		if let Some(v) = b.stored_value.get() {
+			return v;
+		}
+		if let Some(v) = b.endgame_value() {
+			b.stored_value.set(Some(v));
+			return v;
+		}
+		let out = match b.nnue.as_ref() {
+			Some(acc) if Arc::ptr_eq(&acc.net, &self.net) => self.net.output(acc, b.player),
+			// boards that didn't descend from an attached root
+			_ => self.net.output(&Accumulator::new(self.net.clone(), b), b.player),
+		};
+		// stay clear of mate scores
+		let bound = (MATE - MAX_PLY - 1) as i32;
+		let out = out.clamp(-bound, bound) as Value;
+		let v = if b.player == Color::White { out } else { -out };
+		b.stored_value.set(Some(v));
+		v
+	}
+
+	fn attach(&self, b: &mut Board) {
+		b.nnue = Some(Accumulator::new(self.net.clone(), b));
+	}
+
+	fn trace(&self, b: &Board) -> EvalTrace {
+		EvalTrace { evaluation: Evaluation::Nnue, phase: b.game_phase(), endgame: b.endgame_value(), total: self.evaluate(b), ..Default::default() }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use crate::board::{Board,Move};
+	use crate::evaluation::Evaluator;
+	use crate::nnue::{Accumulator,Network,Nnue,FEATURES,MAX_HIDDEN};
+
+	/// A small network with pseudo-random weights
+	fn random_network(hidden: usize) -> Network {
+		let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+		let mut next = |range: i16| -> i16 {
+			state ^= state << 13;
+			state ^= state >> 7;
+			state ^= state << 17;
+			(state % (2 * range as u64 + 1)) as i16 - range
+		};
+		Network {
+			hidden,
+			feature_weights: (0..FEATURES * hidden).map(|_| next(64)).collect(),
+			feature_bias: (0..hidden).map(|_| next(64)).collect(),
+			output_weights: (0..2 * hidden).map(|_| next(64)).collect(),
+			output_bias: 0,
+		}
+	}
+
+	#[test]
+	pub fn test_network_bytes() {
+		let net = random_network(8);
+		debug_assert_eq!(Network::from_bytes(&net.to_bytes()), Ok(net.clone()));
+		debug_assert!(Network::from_bytes(&net.to_bytes()[1..]).is_err());
+		// too large for the accumulators
+		let mut bytes = net.to_bytes();
+		bytes[4..8].copy_from_slice(&(MAX_HIDDEN as u32 + 1).to_le_bytes());
+		debug_assert_eq!(Network::from_bytes(&bytes), Err(String::from("unsupported hidden layer size: 257")));
+	}
+
+	#[test]
+	pub fn test_incremental_update() {
+		let nnue = Nnue::new(Arc::new(random_network(16)));
+		let mut b = Board::from_fen("r3k2r/1P6/8/8/8/5p2/4P3/R3K2R w KQkq - 0 1").unwrap();
+		nnue.attach(&mut b);
+		// castling on both sides, capture, promotion with capture
+		for mv in ["e1c1", "e8g8", "e2f3", "g8h8", "b7a8"] {
+			b = b.clone_apply_move(&mv.parse::<Move>().unwrap());
+			let fresh = Accumulator::new(nnue.net.clone(), &b);
+			debug_assert_eq!(b.nnue.as_ref(), Some(&fresh));
+		}
+		let detached = Board { nnue: None, ..b.clone() };
+		debug_assert_eq!(nnue.evaluate(&b), nnue.evaluate(&detached));
+	}
+}
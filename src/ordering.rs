@@ -2,13 +2,13 @@ use rand::seq::SliceRandom;
 use rand::thread_rng;
 
 use crate::board::{Board, Move};
-use crate::evaluation::{Valuable,Value};
+use crate::evaluation::{Evaluator,Value};
 use crate::misc::*;
 
-pub fn move_ordering(bs: &mut Vec<(Move, Board)>, sign: i8, opts: &Options) {
+pub fn move_ordering(bs: &mut Vec<(Move, Board)>, sign: i8, opts: &Options, eval: &dyn Evaluator) {
 	match opts.move_ordering {
 		MoveOrdering::Eval => {
-			bs.sort_unstable_by(|a, b| (sign as Value * b.1.value()).cmp( &(sign as Value * a.1.value())) );
+			bs.sort_unstable_by(|a, b| (sign as Value * eval.evaluate(&b.1)).cmp( &(sign as Value * eval.evaluate(&a.1))) );
 		},
 		MoveOrdering::Rand => {
 			bs.shuffle(&mut thread_rng());
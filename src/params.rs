@@ -0,0 +1,232 @@
+use std::fmt::Write;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+
+use crate::evaluation::{Value, MG, EG, MG_POS_BONUS, EG_POS_BONUS};
+
+/*
+ * Weights of the evaluation terms, as (middlegame, endgame) pairs in centipawns.
+ * Counting terms (e.g. mobility) are weights per unit, the others per occurrence.
+ *
+ * The built-in defaults can be replaced by a parameter file, in a subset of TOML:
+ * one `name = [mg, eg]` or `name = [[mg, eg], ...]` entry per parameter, `#` comments.
+ * Parameters missing from the file keep their default value.
+*/
+
+/// A (middlegame, endgame) weight
+pub type Weight = (i32, i32);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalParams {
+	/* Material */
+	/// Value of each piece
+	pub piece_value: [Weight; 6],
+	/// Positional bonus of each piece, by square (`row * 8 + col`, from White's side)
+	pub pst: [[Weight; 64]; 6],
+
+	/* Pawn structure */
+	/// Penalty for each extra pawn on a file
+	pub doubled: Weight,
+	/// Penalty for a pawn without friendly pawns on the adjacent files
+	pub isolated: Weight,
+	/// Penalty for a pawn behind its neighbours, that can't safely advance
+	pub backward: Weight,
+	/// Bonus for a passed pawn, by relative rank
+	pub passed: [Weight; 8],
+	/// Extra bonus for a passed pawn with nothing in its way, by relative rank
+	pub passed_free: [Weight; 8],
+
+	/* Pieces */
+	/// Bonus per reachable square, by piece type
+	pub mobility: [Weight; 6],
+	/// Bonus for having both bishops
+	pub bishop_pair: Weight,
+	/// Bonus for a rook on a file without pawns
+	pub rook_open_file: Weight,
+	/// Bonus for a rook on a file without friendly pawns
+	pub rook_semi_open_file: Weight,
+	/// Bonus for a rook on the seventh rank
+	pub rook_seventh: Weight,
+	/// Bonus for a knight in the enemy half, defended by a pawn and out of reach of enemy pawns
+	pub knight_outpost: Weight,
+
+	/* King safety */
+	/// Bonus for each friendly pawn in front of the king
+	pub pawn_shield: Weight,
+	/// Penalty for each file next to the king without friendly pawns
+	pub king_open_file: Weight,
+	/// Penalty for each enemy piece reaching the squares around the king
+	pub king_attacker: Weight,
+}
+
+const fn merge_values(mg: &[Value; 6], eg: &[Value; 6]) -> [Weight; 6] {
+	let mut w = [(0, 0); 6];
+	let mut p = 0;
+	while p < 6 {
+		w[p] = (mg[p] as i32, eg[p] as i32);
+		p += 1;
+	}
+	w
+}
+
+const fn merge_pst(mg: &[[[Value; 8]; 8]; 6], eg: &[[[Value; 8]; 8]; 6]) -> [[Weight; 64]; 6] {
+	let mut w = [[(0, 0); 64]; 6];
+	let mut p = 0;
+	while p < 6 {
+		let mut sq = 0;
+		while sq < 64 {
+			w[p][sq] = (mg[p][sq / 8][sq % 8] as i32, eg[p][sq / 8][sq % 8] as i32);
+			sq += 1;
+		}
+		p += 1;
+	}
+	w
+}
+
+pub static PARAMS_DEFAULT: EvalParams = EvalParams {
+	piece_value: merge_values(&MG, &EG),
+	pst: merge_pst(&MG_POS_BONUS, &EG_POS_BONUS),
+
+	doubled: (-10, -20),
+	isolated: (-10, -15),
+	backward: (-8, -10),
+	passed: [(0, 0), (5, 10), (10, 15), (15, 25), (25, 45), (40, 75), (60, 120), (0, 0)],
+	passed_free: [(0, 0), (0, 5), (0, 5), (0, 10), (0, 20), (0, 35), (0, 60), (0, 0)],
+
+	mobility: [(0, 0), (4, 4), (5, 5), (2, 4), (1, 2), (0, 0)],
+	bishop_pair: (30, 50),
+	rook_open_file: (25, 10),
+	rook_semi_open_file: (10, 5),
+	rook_seventh: (20, 30),
+	knight_outpost: (20, 10),
+
+	pawn_shield: (10, 0),
+	king_open_file: (-15, 0),
+	king_attacker: (-12, 0),
+};
+
+impl EvalParams {
+	/// Every parameter with its name, in file order
+	pub fn weights_mut(&mut self) -> Vec<(&'static str, &mut [Weight])> {
+		let [pawn, knight, bishop, rook, queen, king] = &mut self.pst;
+		vec![
+			("piece_value", &mut self.piece_value[..]),
+			("pst_pawn", &mut pawn[..]),
+			("pst_knight", &mut knight[..]),
+			("pst_bishop", &mut bishop[..]),
+			("pst_rook", &mut rook[..]),
+			("pst_queen", &mut queen[..]),
+			("pst_king", &mut king[..]),
+			("doubled", std::slice::from_mut(&mut self.doubled)),
+			("isolated", std::slice::from_mut(&mut self.isolated)),
+			("backward", std::slice::from_mut(&mut self.backward)),
+			("passed", &mut self.passed[..]),
+			("passed_free", &mut self.passed_free[..]),
+			("mobility", &mut self.mobility[..]),
+			("bishop_pair", std::slice::from_mut(&mut self.bishop_pair)),
+			("rook_open_file", std::slice::from_mut(&mut self.rook_open_file)),
+			("rook_semi_open_file", std::slice::from_mut(&mut self.rook_semi_open_file)),
+			("rook_seventh", std::slice::from_mut(&mut self.rook_seventh)),
+			("knight_outpost", std::slice::from_mut(&mut self.knight_outpost)),
+			("pawn_shield", std::slice::from_mut(&mut self.pawn_shield)),
+			("king_open_file", std::slice::from_mut(&mut self.king_open_file)),
+			("king_attacker", std::slice::from_mut(&mut self.king_attacker)),
+		]
+	}
+
+	/// Writes the parameters in the format read by `from_text`
+	pub fn to_text(&self) -> String {
+		let mut s = String::from("# Duchess evaluation parameters: [middlegame, endgame] pairs in centipawns\n");
+		let mut params = self.clone();
+		for (name, weights) in params.weights_mut() {
+			if weights.len() == 1 {
+				let _ = writeln!(s, "{} = [{}, {}]", name, weights[0].0, weights[0].1);
+				continue;
+			}
+			let _ = writeln!(s, "{} = [", name);
+			for row in weights.chunks(8) {
+				let pairs: Vec<String> = row.iter().map(|(mg, eg)| format!("[{}, {}]", mg, eg)).collect();
+				let _ = writeln!(s, "\t{},", pairs.join(", "));
+			}
+			let _ = writeln!(s, "]");
+		}
+		s
+	}
+
+	/// Reads parameters written by `to_text`. Missing parameters keep their default value
+	pub fn from_text(text: &str) -> Result<EvalParams, String> {
+		let mut params = PARAMS_DEFAULT.clone();
+		// (name, value) entries; a value spans several lines until its brackets are balanced
+		let mut entries: Vec<(String, String)> = Vec::new();
+		let mut depth = 0;
+		for (n, line) in text.lines().enumerate() {
+			let line = line.split('#').next().unwrap_or("").trim();
+			if line.is_empty() {
+				continue;
+			}
+			if depth == 0 {
+				match line.split_once('=') {
+					Some((name, value)) => entries.push((name.trim().to_string(), value.to_string())),
+					None => return Err(format!("line {}: expected `name = value`", n + 1)),
+				}
+			} else if let Some((_, value)) = entries.last_mut() {
+				value.push_str(line);
+			}
+			depth = entries.last().map_or(0, |(_, v)| v.matches('[').count() as i32 - v.matches(']').count() as i32);
+		}
+		if depth != 0 {
+			return Err(String::from("unbalanced brackets at the end of the file"));
+		}
+
+		for (name, value) in entries {
+			let numbers: Result<Vec<i32>, _> = value
+				.split(|c: char| c == '[' || c == ']' || c == ',' || c.is_whitespace())
+				.filter(|t| !t.is_empty())
+				.map(|t| t.parse::<i32>())
+				.collect();
+			let numbers = numbers.map_err(|_| format!("{}: not a list of integers", name))?;
+			let mut fields = params.weights_mut();
+			let weights = match fields.iter_mut().find(|(n, _)| *n == name) {
+				Some((_, w)) => w,
+				None => return Err(format!("unknown parameter: {}", name)),
+			};
+			if numbers.len() != 2 * weights.len() {
+				return Err(format!("{}: expected {} values, found {}", name, 2 * weights.len(), numbers.len()));
+			}
+			for (w, pair) in weights.iter_mut().zip(numbers.chunks(2)) {
+				*w = (pair[0], pair[1]);
+			}
+		}
+		Ok(params)
+	}
+
+	/// Loads a parameter file
+	#[cfg(not(target_arch = "wasm32"))]
+	#[allow(dead_code)]
+	pub fn load(path: &str) -> Result<EvalParams, String> {
+		let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+		EvalParams::from_text(&text).map_err(|e| format!("{}: {}", path, e))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::params::{EvalParams,PARAMS_DEFAULT};
+
+	#[test]
+	pub fn test_round_trip() {
+		let mut params = PARAMS_DEFAULT.clone();
+		params.bishop_pair = (42, -7);
+		params.pst[3][62] = (1, 2);
+		debug_assert_eq!(EvalParams::from_text(&params.to_text()), Ok(params));
+	}
+
+	#[test]
+	pub fn test_partial_and_invalid() {
+		let params = EvalParams::from_text("# only this one\nrook_seventh = [1, 2]\n").unwrap();
+		debug_assert_eq!(params.rook_seventh, (1, 2));
+		debug_assert_eq!(params.piece_value, PARAMS_DEFAULT.piece_value);
+		debug_assert!(EvalParams::from_text("no_such_thing = [1, 2]").is_err());
+		debug_assert!(EvalParams::from_text("doubled = [1, 2, 3]").is_err());
+	}
+}
@@ -0,0 +1,208 @@
+use std::cell::RefCell;
+
+use crate::board::{Board, Color, Piece, Pos};
+use crate::params::{EvalParams, Weight};
+
+/*
+ * Pawn structure evaluation: doubled, isolated, backward and passed pawns.
+ * https://www.chessprogramming.org/Pawn_Structure
+ * The structure only depends on where the pawns are, so it is cached in a
+ * pawn hash table keyed on the pawn configuration:
+ * https://www.chessprogramming.org/Pawn_Hash_Table
+ * The table holds counts rather than scores, so that it stays valid whatever
+ * the evaluation parameters.
+*/
+
+/* Number of entries of the pawn hash table (per thread) */
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+/// Pawn structure of a position
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PawnInfo {
+	/// Extra pawns on the same file, per color
+	pub doubled: [i32; 2],
+	/// Isolated pawns, per color
+	pub isolated: [i32; 2],
+	/// Backward pawns, per color
+	pub backward: [i32; 2],
+	/// Passed pawns per color, one bit per square (`row * 8 + col`)
+	pub passed: [u64; 2],
+	/// Pawns per color and file, one bit per row
+	pub files: [[u8; 8]; 2],
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct PawnEntry {
+	key: u64,
+	info: PawnInfo,
+}
+
+thread_local! {
+	static PAWN_TABLE: RefCell<Vec<PawnEntry>> = RefCell::new(vec![PawnEntry::default(); PAWN_TABLE_SIZE]);
+}
+
+#[inline]
+pub fn forward(color: Color) -> i8 {
+	match color {
+		Color::White => 1,
+		Color::Black => -1,
+	}
+}
+
+/// Rank as seen from the given color: 0 is its back rank
+#[inline]
+pub fn relative_row(color: Color, row: i8) -> usize {
+	match color {
+		Color::White => row as usize,
+		Color::Black => (7 - row) as usize,
+	}
+}
+
+/// Rows strictly ahead of the given one from the given color's side, one bit per row
+#[inline]
+pub fn rows_ahead(color: Color, row: i8) -> u8 {
+	match color {
+		Color::White => !((2u16 << row) - 1) as u8,
+		Color::Black => ((1u16 << row) - 1) as u8,
+	}
+}
+
+/// Analyses the pawn structure from scratch
+fn analyse(b: &Board) -> PawnInfo {
+	// pawns[color][file] has bit `row` set for each pawn on that file
+	let mut pawns = [[0u8; 10]; 2];
+	for c in 0..8 {
+		for r in 0..8 {
+			if let Some(tile) = b.at(Pos::at(c, r).unwrap()) {
+				if tile.piece == Piece::Pawn {
+					pawns[tile.color as usize][c as usize + 1] |= 1 << r;
+				}
+			}
+		}
+	}
+	// files are shifted by one, so that the adjacent files of a and h exist (and are empty)
+	let file = |color: Color, col: i8| pawns[color as usize][(col + 1) as usize];
+
+	let mut info = PawnInfo::default();
+	for color in [Color::White, Color::Black] {
+		info.files[color as usize].copy_from_slice(&pawns[color as usize][1..9]);
+	}
+	for color in [Color::White, Color::Black] {
+		let us = color as usize;
+		let them = color.swap();
+		let fwd = forward(color);
+		for c in 0..8i8 {
+			let on_file = file(color, c);
+			if on_file.count_ones() > 1 {
+				info.doubled[us] += on_file.count_ones() as i32 - 1;
+			}
+			for r in 0..8i8 {
+				if on_file & (1 << r) == 0 {
+					continue;
+				}
+				let ahead = rows_ahead(color, r);
+				let behind_or_level = !ahead;
+				let neighbours = file(color, c - 1) | file(color, c + 1);
+
+				let isolated = neighbours == 0;
+				if isolated {
+					info.isolated[us] += 1;
+				}
+
+				let passed = (file(them, c - 1) | file(them, c) | file(them, c + 1)) & ahead == 0;
+				if passed {
+					info.passed[us] |= 1 << (r * 8 + c);
+				} else if !isolated && neighbours & behind_or_level == 0 {
+					// all the neighbours are ahead: backward if the stop square is guarded by an enemy pawn
+					let stop = r + fwd;
+					let guard = stop + fwd;
+					if (0..8).contains(&guard) && (file(them, c - 1) | file(them, c + 1)) & (1 << guard) != 0 {
+						info.backward[us] += 1;
+					}
+				}
+			}
+		}
+	}
+	info
+}
+
+/// Pawn structure of the board, looked up in the pawn hash table when possible
+pub fn pawn_info(b: &Board) -> PawnInfo {
+	let key = b.pawn_hash();
+	PAWN_TABLE.with(|table| {
+		let mut table = table.borrow_mut();
+		let entry = &mut table[key as usize % PAWN_TABLE_SIZE];
+		if entry.key != key || key == 0 {
+			*entry = PawnEntry { key, info: analyse(b) };
+		}
+		entry.info
+	})
+}
+
+/// Pawn structure score as (middlegame, endgame) per color.
+/// Passed pawns are scored by rank, with an extra bonus when their path to promotion is free
+pub fn pawn_structure(b: &Board, info: &PawnInfo, params: &EvalParams) -> [Weight; 2] {
+	let mut scores = [(0, 0); 2];
+	for color in [Color::White, Color::Black] {
+		let score = &mut scores[color as usize];
+		for (w, n) in [(params.doubled, info.doubled), (params.isolated, info.isolated), (params.backward, info.backward)] {
+			score.0 += w.0 * n[color as usize];
+			score.1 += w.1 * n[color as usize];
+		}
+		let mut passed = info.passed[color as usize];
+		while passed != 0 {
+			let sq = passed.trailing_zeros() as i8;
+			passed &= passed - 1;
+			let (c, r) = (sq % 8, sq / 8);
+			let rr = relative_row(color, r);
+			score.0 += params.passed[rr].0;
+			score.1 += params.passed[rr].1;
+			let fwd = forward(color);
+			let mut row = r + fwd;
+			let mut free = true;
+			while (0..8).contains(&row) {
+				if b.at(Pos::at(c, row).unwrap()).is_some() {
+					free = false;
+					break;
+				}
+				row += fwd;
+			}
+			if free {
+				score.0 += params.passed_free[rr].0;
+				score.1 += params.passed_free[rr].1;
+			}
+		}
+	}
+	scores
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::board::{Board,Color};
+	use crate::pawns::{pawn_info,pawn_structure};
+	use crate::params::PARAMS_DEFAULT;
+
+	#[test]
+	pub fn test_doubled_isolated() {
+		// white: doubled and isolated pawns on the c file, black: a duo with a passed pawn on e5
+		let b = Board::from_fen("4k3/8/8/3pp3/8/2P5/2P5/4K3 w - - 0 0").unwrap();
+		let p = &PARAMS_DEFAULT;
+		let info = pawn_info(&b);
+		debug_assert_eq!(info.passed, [1 << (4 * 8 + 4), 0]);
+		debug_assert_eq!((info.doubled, info.isolated, info.backward), ([0, 1], [0, 2], [0, 0]));
+		let scores = pawn_structure(&b, &info, p);
+		debug_assert_eq!(scores[Color::White as usize].0, p.doubled.0 + 2 * p.isolated.0);
+		debug_assert_eq!(scores[Color::Black as usize].0, p.passed[3].0);
+	}
+
+	#[test]
+	pub fn test_passed_free_path() {
+		let free = Board::from_fen("4k3/8/1P6/8/8/8/8/4K3 w - - 0 0").unwrap();
+		let blocked = Board::from_fen("1n2k3/8/1P6/8/8/8/8/4K3 w - - 0 0").unwrap();
+		let p = &PARAMS_DEFAULT;
+		let (free_info, blocked_info) = (pawn_info(&free), pawn_info(&blocked));
+		debug_assert_eq!(free_info.passed[1], 1 << (5 * 8 + 1));
+		let white = Color::White as usize;
+		debug_assert!(pawn_structure(&free, &free_info, p)[white].1 > pawn_structure(&blocked, &blocked_info, p)[white].1);
+	}
+}
@@ -0,0 +1,441 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::board::{Board, Color, GameEnd, Move};
+use crate::evaluation::{Value, mate_distance};
+use crate::misc::Options;
+use crate::san::SanError;
+
+/*
+ * Portable Game Notation: http://www.saremo.com/pgn/pgn_standard.txt (section 8)
+ * Only the main line of each game is kept: comments, NAGs, variations and move numbers are skipped.
+ * Games start from the `[FEN]` tag when there is one, and are replayed move by move to validate them.
+ * Games are written in export format (section 3.2): the seven tag roster first, SAN moves,
+ * lines of at most 80 characters.
+*/
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+const LINE_LENGTH: usize = 80;
+
+/// A game as read from a PGN file, with its moves still in algebraic notation
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PgnGame {
+	/// Tag pairs, in file order
+	pub tags: Vec<(String, String)>,
+	/// Moves of the main line, in algebraic notation
+	pub moves: Vec<String>,
+	/// Game termination marker: `1-0`, `0-1`, `1/2-1/2` or `*`
+	pub result: String,
+}
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+impl PgnGame {
+	pub fn tag(&self, name: &str) -> Option<&str> {
+		self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+	}
+
+	/// Points scored by White: 1 for a win, 0.5 for a draw, `None` if the game didn't end
+	pub fn white_score(&self) -> Option<f64> {
+		match self.result.as_str() {
+			"1-0" => Some(1.0),
+			"0-1" => Some(0.0),
+			"1/2-1/2" => Some(0.5),
+			_ => None,
+		}
+	}
+}
+
+/// Why a game of a PGN file doesn't replay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgnErrorKind {
+	/// The `[FEN]` tag is not a valid position
+	Fen(String),
+	/// A move is invalid, illegal or ambiguous
+	Move(String, SanError),
+}
+
+/// A game that doesn't replay, numbered from 1 in its file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgnError {
+	pub game: usize,
+	/// Half-move of the error, from 1; 0 for the set-up position
+	pub ply: usize,
+	pub kind: PgnErrorKind,
+}
+
+impl fmt::Display for PgnError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match &self.kind {
+			PgnErrorKind::Fen(e) => write!(f, "game {}: FEN tag: {}", self.game, e),
+			PgnErrorKind::Move(san, e) => write!(f, "game {}, ply {}: {}: {}", self.game, self.ply, san, e),
+		}
+	}
+}
+
+impl PgnGame {
+	/// Plays the moves from the start position, checking each against the legal moves.
+	/// `number` is the number of the game in its file, for the error
+	pub fn replay(&self, number: usize) -> Result<GameRecord, PgnError> {
+		let start = match self.tag("FEN") {
+			None => Board::new(Color::White),
+			Some(fen) => Board::from_fen(fen)
+				.map_err(|e| PgnError { game: number, ply: 0, kind: PgnErrorKind::Fen(e.to_string()) })?,
+		};
+		let mut game = GameRecord {
+			tags: self.tags.clone(),
+			start_fen: self.tag("FEN").unwrap_or(START_FEN).to_string(),
+			moves: Vec::new(),
+			result: self.result.clone(),
+		};
+		// the set-up is in `start_fen`
+		game.tags.retain(|(n, _)| n != "FEN" && n != "SetUp");
+		let mut b = start;
+		for (ply, san) in self.moves.iter().enumerate() {
+			let mv = b.parse_san(san)
+				.map_err(|e| PgnError { game: number, ply: ply + 1, kind: PgnErrorKind::Move(san.clone(), e) })?;
+			game.push(mv, None, 0);
+			b = b.clone_apply_move(&mv);
+		}
+		Ok(game)
+	}
+}
+
+/// Reads and replays the games of a PGN file, keeping those that are valid
+pub fn load_games(text: &str) -> (Vec<GameRecord>, Vec<PgnError>) {
+	let mut games = Vec::new();
+	let mut errors = Vec::new();
+	for (n, game) in read_games(text).iter().enumerate() {
+		match game.replay(n + 1) {
+			Ok(g) => games.push(g),
+			Err(e) => errors.push(e),
+		}
+	}
+	(games, errors)
+}
+
+/// A move played by the engine, with what the search said about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordedMove {
+	pub mv: Move,
+	/// From White's point of view, `None` for moves that were not searched (book, tablebases)
+	pub score: Option<Value>,
+	pub depth: u8,
+}
+
+/// A game played by the engine, to be written as PGN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameRecord {
+	/// Tag pairs, the seven tag roster first
+	pub tags: Vec<(String, String)>,
+	pub start_fen: String,
+	pub moves: Vec<RecordedMove>,
+	/// Game termination marker, `*` while the game goes on
+	pub result: String,
+}
+
+impl GameRecord {
+	/// A game from the given position, dated today, with unknown players.
+	/// The board doesn't keep the move counters: the game starts at move 1
+	pub fn new(start: &Board) -> Self {
+		let tags = [("Event", "?"), ("Site", "?"), ("Date", &today()), ("Round", "-"), ("White", "?"), ("Black", "?"), ("Result", "*")];
+		GameRecord {
+			tags: tags.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect(),
+			start_fen: format!("{} 0 1", start.to_fen().split_whitespace().take(4).collect::<Vec<_>>().join(" ")),
+			moves: Vec::new(),
+			result: String::from("*"),
+		}
+	}
+
+	/// Sets a tag, replacing its value if already there
+	pub fn set_tag(&mut self, name: &str, value: &str) {
+		match self.tags.iter_mut().find(|(n, _)| n == name) {
+			Some(tag) => tag.1 = value.to_string(),
+			None => self.tags.push((name.to_string(), value.to_string())),
+		}
+	}
+
+	/// Records the search settings, and the time control they amount to
+	pub fn set_options(&mut self, opts: &Options) {
+		let desc = format!("algo={:?} ordering={:?} eval={:?} depth={} threads={} alphabeta={} nullmove={} lmr={} aspiration={} book={} syzygy={}",
+			opts.search_algo, opts.move_ordering, opts.eval, opts.max_depth, opts.threads, opts.alpha_beta,
+			opts.null_move_pruning, opts.late_move_reductions, opts.aspiration, opts.own_book, opts.syzygy.is_some());
+		self.set_tag("EngineOptions", &desc.to_lowercase());
+		// fixed depth, no clock
+		self.set_tag("TimeControl", "-");
+	}
+
+	pub fn push(&mut self, mv: Move, score: Option<Value>, depth: u8) {
+		self.moves.push(RecordedMove { mv, score, depth });
+	}
+
+	/// Ends the game with the result of the final position, or the given one if it isn't over
+	pub fn finish(&mut self, otherwise: &str) {
+		let end = Board::from_fen(&self.start_fen).ok().and_then(|mut b| {
+			for m in &self.moves {
+				b = b.clone_apply_move(&m.mv);
+			}
+			b.check_end_game()
+		});
+		self.result = match end {
+			Some(GameEnd::Checkmate(Color::White)) => "0-1",
+			Some(GameEnd::Checkmate(Color::Black)) => "1-0",
+			Some(GameEnd::Draw) => "1/2-1/2",
+			None => otherwise,
+		}.to_string();
+		let result = self.result.clone();
+		self.set_tag("Result", &result);
+	}
+
+	/// The game in PGN export format, with `{score/depth}` comments after the searched moves
+	/// if `comments` is set. Scores are in pawns from the mover's point of view, `M` for mates
+	pub fn to_pgn(&self, comments: bool) -> String {
+		let mut out = String::new();
+		for (name, value) in &self.tags {
+			out.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+		}
+		// the board doesn't keep the move counters
+		if !self.start_fen.split_whitespace().take(4).eq(START_FEN.split_whitespace().take(4)) {
+			out.push_str(&format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", self.start_fen));
+		}
+		out.push('\n');
+
+		let mut tokens = Vec::new();
+		let mut b = match Board::from_fen(&self.start_fen) {
+			Ok(b) => b,
+			Err(_) => return out,
+		};
+		let mut number: u32 = self.start_fen.split_whitespace().nth(5).and_then(|n| n.parse().ok()).unwrap_or(1).max(1);
+		// Black's moves need a number at the start and after a comment
+		let mut need_number = true;
+		for m in &self.moves {
+			if b.player == Color::White {
+				tokens.push(format!("{}.", number));
+			} else if need_number {
+				tokens.push(format!("{}...", number));
+			}
+			tokens.push(b.move_to_san(&m.mv));
+			need_number = false;
+			if let (true, Some(score)) = (comments, m.score) {
+				let score = if b.player == Color::White { score } else { -score };
+				let text = match mate_distance(score) {
+					Some(d) if d > 0 => format!("+M{}", d),
+					Some(d) => format!("-M{}", -d),
+					None => format!("{:+.2}", score as f64 / 100.0),
+				};
+				tokens.push(format!("{{{}/{}}}", text, m.depth));
+				need_number = true;
+			}
+			if b.player == Color::Black {
+				number += 1;
+			}
+			b = b.clone_apply_move(&m.mv);
+		}
+		tokens.push(self.result.clone());
+
+		let mut line = String::new();
+		for token in tokens {
+			if !line.is_empty() && line.len() + 1 + token.len() > LINE_LENGTH {
+				out.push_str(&line);
+				out.push('\n');
+				line.clear();
+			}
+			if !line.is_empty() {
+				line.push(' ');
+			}
+			line.push_str(&token);
+		}
+		out.push_str(&line);
+		out.push_str("\n\n");
+		out
+	}
+}
+
+/// Today's date as `YYYY.MM.DD`, in UTC. PGN support is not part of the Wasm library,
+/// so `SystemTime::now()`, which panics in the browser, is fine here
+fn today() -> String {
+	let days = match SystemTime::now().duration_since(UNIX_EPOCH) {
+		Ok(d) => (d.as_secs() / 86_400) as i64,
+		Err(_) => return String::from("????.??.??"),
+	};
+	// civil from days: http://howardhinnant.github.io/date_algorithms.html
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+/// Splits the text of a PGN file into games
+pub fn read_games(text: &str) -> Vec<PgnGame> {
+	let mut games = Vec::new();
+	let mut game = PgnGame::default();
+	// nesting of `{}` comments and `()` variations
+	let mut comment = false;
+	let mut variation: u32 = 0;
+	for line in text.lines() {
+		let line = line.trim();
+		if !comment && variation == 0 && line.starts_with('[') {
+			// a tag after some moves starts a new game
+			if !game.moves.is_empty() {
+				games.push(std::mem::take(&mut game));
+			}
+			if let Some(tag) = parse_tag(line) {
+				game.tags.push(tag);
+			}
+			continue;
+		}
+		if line.starts_with('%') {
+			continue;
+		}
+		let mut token = String::new();
+		let mut chars = line.chars().peekable();
+		while let Some(c) = chars.next() {
+			if comment {
+				comment = c != '}';
+				continue;
+			}
+			match c {
+				'{' => comment = true,
+				// rest of line comment
+				';' => break,
+				'(' => variation += 1,
+				// a stray `)` doesn't hide the rest of the file
+				')' => variation = variation.saturating_sub(1),
+				c if c.is_whitespace() => (),
+				_ => {
+					token.push(c);
+					if chars.peek().is_none_or(|n| n.is_whitespace() || "{;()".contains(*n)) {
+						if variation == 0 {
+							add_token(&mut game, &token);
+							if !game.result.is_empty() {
+								games.push(std::mem::take(&mut game));
+							}
+						}
+						token.clear();
+					}
+				},
+			}
+		}
+	}
+	if !game.moves.is_empty() {
+		games.push(game);
+	}
+	games
+}
+
+/// Parses `[Name "value"]`
+fn parse_tag(line: &str) -> Option<(String, String)> {
+	let inner = line.strip_prefix('[')?.strip_suffix(']')?.trim();
+	let (name, value) = inner.split_once(char::is_whitespace)?;
+	let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+	Some((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+/// Adds a movetext token to the game, skipping move numbers and NAGs
+fn add_token(game: &mut PgnGame, token: &str) {
+	if RESULTS.contains(&token) {
+		game.result = token.to_string();
+		return;
+	}
+	// `12.`, `12...`, or glued to the move as in `12.e4`, but not castling as `0-0`
+	let number = token.trim_start_matches(|c: char| c.is_ascii_digit());
+	let mv = if number.starts_with('.') { number.trim_start_matches('.') } else { token };
+	if mv.is_empty() || mv.starts_with('$') {
+		return;
+	}
+	game.moves.push(mv.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::board::{Board,Color,Move};
+	use crate::misc::OPTS_DEFAULT;
+	use crate::pgn::{GameRecord,PgnErrorKind,load_games,read_games};
+	use crate::san::SanError;
+
+	#[test]
+	pub fn test_read_games() {
+		let text = "[Event \"Test\"]\n[White \"A\"]\n\n1. e4 {best by test} e5 2.Nf3 $1 (2. f4 exf4) Nc6; comment\n3. Bb5 1-0\n\n\
+			[Event \"Second\"]\n\n1. d4 d5 2. Nf3 Nf6 3. g3 g6 4. Bg2 Bg7 5. 0-0 5... 0-0 *\n";
+		let games = read_games(text);
+		debug_assert_eq!(games.len(), 2);
+		debug_assert_eq!(games[0].tag("White"), Some("A"));
+		debug_assert_eq!(games[0].moves, vec!["e4", "e5", "Nf3", "Nc6", "Bb5"]);
+		debug_assert_eq!(games[0].white_score(), Some(1.0));
+		debug_assert_eq!(games[1].moves, vec!["d4", "d5", "Nf3", "Nf6", "g3", "g6", "Bg2", "Bg7", "0-0", "0-0"]);
+		debug_assert!(games[1].replay(2).is_ok());
+		debug_assert_eq!(games[1].white_score(), None);
+
+		// an unbalanced variation
+		let games = read_games("1. e4 e5 2. f4) exf4 1-0
+
+[Event \"Next\"]
+
+1. d4 *
+");
+		debug_assert_eq!(games.len(), 2);
+		debug_assert_eq!(games[0].moves, vec!["e4", "e5", "f4", "exf4"]);
+		debug_assert_eq!(games[0].result, "1-0");
+		debug_assert_eq!(games[1].tag("Event"), Some("Next"));
+	}
+	#[test]
+	pub fn test_write_game() {
+		let mut game = GameRecord::new(&Board::new(Color::White));
+		game.set_tag("White", "Duchess \"A\"");
+		game.set_options(&OPTS_DEFAULT);
+		for (mv, score) in [("f2f3", Some(-20)), ("e7e5", None), ("g2g4", Some(-150)), ("d8h4", Some(-29999))] {
+			game.push(mv.parse::<Move>().unwrap(), score, 4);
+		}
+		game.finish("*");
+		let pgn = game.to_pgn(true);
+		debug_assert!(pgn.starts_with("[Event \"?\"]\n[Site \"?\"]\n[Date \""));
+		debug_assert!(game.tags.iter().any(|(n, v)| n == "Date" && v.len() == 10 && !v.starts_with('?')));
+		debug_assert!(pgn.contains("[White \"Duchess \\\"A\\\"\"]\n"));
+		debug_assert!(pgn.contains("[Result \"0-1\"]\n"));
+		debug_assert!(pgn.ends_with("\n\n1. f3 {-0.20/4} 1... e5 2. g4 {-1.50/4} 2... Qh4# {+M1/4} 0-1\n\n"));
+		debug_assert!(GameRecord { moves: Vec::new(), ..game.clone() }.to_pgn(false).ends_with("]\n\n0-1\n\n"));
+		// written games read back
+		let games = read_games(&pgn);
+		debug_assert_eq!(games.len(), 1);
+		debug_assert_eq!(games[0].moves, vec!["f3", "e5", "g4", "Qh4#"]);
+		debug_assert_eq!(games[0].tag("White"), Some("Duchess \"A\""));
+		// a black move first, from a set-up position
+		let mut game = GameRecord::new(&Board::from_fen("4k3/8/8/8/8/8/8/R3K3 b - - 0 1").unwrap());
+		game.push("e8d7".parse::<Move>().unwrap(), None, 0);
+		game.finish("*");
+		let pgn = game.to_pgn(true);
+		debug_assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/8/R3K3 b - - 0 1\"]\n"));
+		debug_assert!(pgn.ends_with("\n\n1... Kd7 *\n\n"));
+	}
+	#[test]
+	pub fn test_load_games() {
+		let text = "[Event \"One\"]\n1. e4 {a comment (with parentheses)} e5 $2 2. Nf3 (2. Bc4 Nf6 (2... Bc5)) Nc6?! 3. Bb5 a6 *\n\n\
+			[Event \"Two\"]\n1. e4 e5 2. Ke3 1-0\n\n\
+			[Event \"Three\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4K3/R6R w - - 0 40\"]\n40. Rd1 Ke7 41. Rd7+ 1-0\n\n\
+			[Event \"Four\"]\n[FEN \"4k3/8/8/8/8/8/8/R3K2R w - - 0 40\"]\n40. Rh2 Kd7 41. Rd2+ Ke6 42. Rad1 *\n\n\
+			[Event \"Five\"]\n[FEN \"not a position\"]\n1. e4 *\n";
+		let (games, errors) = load_games(text);
+		debug_assert_eq!(games.len(), 2);
+		debug_assert_eq!(games[0].moves.len(), 6);
+		debug_assert_eq!(games[1].start_fen, "4k3/8/8/8/8/8/8/R3K2R w - - 0 40");
+		debug_assert_eq!(games[1].moves.len(), 5);
+		debug_assert_eq!(games[1].tags, vec![(String::from("Event"), String::from("Four"))]);
+		debug_assert_eq!(errors.len(), 3);
+		debug_assert_eq!((errors[0].game, errors[0].ply), (2, 3));
+		debug_assert_eq!(errors[0].kind, PgnErrorKind::Move(String::from("Ke3"), SanError::Illegal));
+		debug_assert_eq!(errors[0].to_string(), "game 2, ply 3: Ke3: illegal move");
+		debug_assert_eq!((errors[1].game, errors[1].ply), (3, 1));
+		debug_assert_eq!(errors[1].kind, PgnErrorKind::Move(String::from("Rd1"), SanError::Ambiguous));
+		debug_assert_eq!(errors[2].to_string(), "game 5: FEN tag: piece placement: 1 rank instead of 8");
+		// written back, the games read the same
+		let again = load_games(&games[1].to_pgn(false)).0;
+		debug_assert_eq!(again[0].moves, games[1].moves);
+		debug_assert_eq!(again[0].start_fen, "4k3/8/8/8/8/8/8/R3K2R w - - 0 40");
+	}
+}
@@ -0,0 +1,164 @@
+use crate::board::{Board, Color, Piece, Pos};
+use crate::params::{EvalParams, Weight};
+use crate::pawns::{PawnInfo, forward, relative_row, rows_ahead};
+
+/*
+ * Positional terms beyond piece-square tables:
+ * mobility https://www.chessprogramming.org/Mobility
+ * king safety https://www.chessprogramming.org/King_Safety
+ * and a few piece-specific bonuses (bishop pair, rooks on open files and on the seventh rank, knight outposts).
+ * Reachable squares are taken from the move generator, so they do not include
+ * squares occupied by friendly pieces.
+*/
+
+/// Pawns of `color` on file `col`, or none if the file is off the board
+#[inline]
+fn file(pawns: &PawnInfo, color: Color, col: i8) -> u8 {
+	if (0..8).contains(&col) { pawns.files[color as usize][col as usize] } else { 0 }
+}
+
+/// Positional terms, as (middlegame, endgame) per color
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PositionalTerms {
+	pub mobility: [Weight; 2],
+	pub king_safety: [Weight; 2],
+	/// Bishop pair, rooks on (semi-)open files and on the seventh rank, knight outposts
+	pub pieces: [Weight; 2],
+}
+
+#[inline]
+fn add(score: &mut Weight, w: Weight, n: i32) {
+	score.0 += w.0 * n;
+	score.1 += w.1 * n;
+}
+
+/// Whether `pos` is the king square or next to it
+#[inline]
+fn in_king_zone(king: Pos, pos: Pos) -> bool {
+	(king.col - pos.col).abs() <= 1 && (king.row - pos.row).abs() <= 1
+}
+
+fn is_knight_outpost(b: &Board, pawns: &PawnInfo, color: Color, pos: Pos) -> bool {
+	let rr = relative_row(color, pos.row);
+	if !(3..=5).contains(&rr) {
+		return false;
+	}
+	let them = color.swap();
+	// no enemy pawn can ever chase the knight away
+	if (file(pawns, them, pos.col - 1) | file(pawns, them, pos.col + 1)) & rows_ahead(color, pos.row) != 0 {
+		return false;
+	}
+	// defended by a friendly pawn
+	[-1, 1].iter().any(|i| match Pos::at(pos.col + i, pos.row - forward(color)) {
+		Some(p) => matches!(b.at(p), Some(t) if t.color == color && t.piece == Piece::Pawn),
+		None => false,
+	})
+}
+
+/// Pawn shield and open files in front of the king
+fn king_shelter(pawns: &PawnInfo, color: Color, king: Pos, params: &EvalParams, score: &mut Weight) {
+	let fwd = forward(color);
+	for col in king.col - 1..=king.col + 1 {
+		if !(0..8).contains(&col) {
+			continue;
+		}
+		let own = file(pawns, color, col);
+		if own == 0 {
+			add(score, params.king_open_file, 1);
+		}
+		let shield = [king.row + fwd, king.row + 2 * fwd].iter()
+			.filter(|r| (0..8).contains(*r) && own & (1 << **r) != 0)
+			.count();
+		add(score, params.pawn_shield, shield as i32);
+	}
+}
+
+/// Positional terms of the board
+pub fn positional(b: &Board, pawns: &PawnInfo, params: &EvalParams) -> PositionalTerms {
+	let mut terms = PositionalTerms::default();
+	// king attackers, by color of the attacked king
+	let mut attackers = [0; 2];
+	let mut bishops = [0; 2];
+
+	for c in 0..8 {
+		for r in 0..8 {
+			let pos = Pos::at(c, r).unwrap();
+			let tile = match b.at(pos) {
+				Some(tile) if tile.piece != Piece::Pawn && tile.piece != Piece::King => tile,
+				_ => continue,
+			};
+			let us = tile.color;
+			let them = us.swap();
+			let score = &mut terms.pieces[us as usize];
+
+			let moves = b.generate(pos);
+			add(&mut terms.mobility[us as usize], params.mobility[tile.piece as usize], moves.len() as i32);
+			let enemy_king = b.king_pos[them as usize];
+			if moves.iter().any(|p| in_king_zone(enemy_king, *p)) {
+				attackers[them as usize] += 1;
+			}
+
+			match tile.piece {
+				Piece::Bishop => bishops[us as usize] += 1,
+				Piece::Rook => {
+					if file(pawns, us, c) == 0 {
+						let w = if file(pawns, them, c) == 0 { params.rook_open_file } else { params.rook_semi_open_file };
+						add(score, w, 1);
+					}
+					if relative_row(us, r) == 6 {
+						add(score, params.rook_seventh, 1);
+					}
+				},
+				Piece::Knight if is_knight_outpost(b, pawns, us, pos) => add(score, params.knight_outpost, 1),
+				_ => (),
+			}
+		}
+	}
+
+	for color in [Color::White, Color::Black] {
+		if bishops[color as usize] >= 2 {
+			add(&mut terms.pieces[color as usize], params.bishop_pair, 1);
+		}
+		let king_safety = &mut terms.king_safety[color as usize];
+		king_shelter(pawns, color, b.king_pos[color as usize], params, king_safety);
+		add(king_safety, params.king_attacker, attackers[color as usize]);
+	}
+	terms
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::board::Board;
+	use crate::params::PARAMS_DEFAULT;
+	use crate::pawns::pawn_info;
+	use crate::positional::positional;
+
+	/// Sum of the positional terms, as (middlegame, endgame) from White's point of view
+	fn score(fen: &str) -> (i32, i32) {
+		let b = Board::from_fen(fen).unwrap();
+		let t = positional(&b, &pawn_info(&b), &PARAMS_DEFAULT);
+		[t.mobility, t.king_safety, t.pieces].iter()
+			.fold((0, 0), |(mg, eg), [black, white]| (mg + white.0 - black.0, eg + white.1 - black.1))
+	}
+
+	#[test]
+	pub fn test_symmetry() {
+		debug_assert_eq!(score("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0"), (0, 0));
+	}
+
+	#[test]
+	pub fn test_rook_open_file() {
+		let open = score("4k3/p7/8/8/8/8/P7/3RK3 w - - 0 0");
+		let closed = score("4k3/p7/8/8/8/8/P7/R3K3 w - - 0 0");
+		debug_assert!(open.0 > closed.0);
+	}
+
+	#[test]
+	pub fn test_pawn_shield() {
+		let sheltered = score("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 0");
+		let advanced = score("6k1/5ppp/8/8/5PPP/8/8/6K1 w - - 0 0");
+		let stripped = score("6k1/5ppp/8/8/8/8/8/6K1 w - - 0 0");
+		debug_assert_eq!(sheltered, (0, 0));
+		debug_assert!(sheltered.0 > advanced.0 && advanced.0 > stripped.0);
+	}
+}
@@ -0,0 +1,207 @@
+use std::fmt;
+
+use crate::board::{Board, Move, Piece, Pos};
+
+/*
+ * Standard algebraic notation: https://www.chessprogramming.org/Algebraic_Chess_Notation
+ * e.g. `e4`, `Nbd7`, `exd5`, `R1e2`, `e8=Q`, `O-O-O`, with optional `+`/`#` and `!`/`?` suffixes
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanError {
+	/// Not a move in algebraic notation
+	Syntax,
+	/// No legal move matches
+	Illegal,
+	/// More than one legal move matches
+	Ambiguous,
+	/// Promotion to a piece other than a queen, which the board doesn't support
+	Underpromotion,
+}
+
+impl fmt::Display for SanError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SanError::Syntax => write!(f, "invalid move syntax"),
+			SanError::Illegal => write!(f, "illegal move"),
+			SanError::Ambiguous => write!(f, "ambiguous move"),
+			SanError::Underpromotion => write!(f, "promotion to a piece other than a queen is not supported"),
+		}
+	}
+}
+
+impl Board {
+	/// Writes a legal move in algebraic notation, e.g. `Nbd7`, `exd5`, `e8=Q+` or `O-O#`
+	pub fn move_to_san(&self, mv: &Move) -> String {
+		let piece = self.at(mv.f_pos).map_or(Piece::Pawn, |t| t.piece);
+		let mut san = String::new();
+		if piece == Piece::King && (mv.f_pos.col - mv.t_pos.col).abs() == 2 {
+			san.push_str(if mv.t_pos.col == 6 { "O-O" } else { "O-O-O" });
+		} else {
+			let file = |pos: Pos| (b'a' + pos.col as u8) as char;
+			let capture = self.at(mv.t_pos).is_some();
+			if piece == Piece::Pawn {
+				if capture {
+					san.push(file(mv.f_pos));
+				}
+			} else {
+				san.push(piece.as_char().to_ascii_uppercase());
+				/* Disambiguation: the file if enough, else the rank, else both */
+				let others: Vec<Pos> = self.generate_all().iter()
+					.map(|(m, _)| *m)
+					.filter(|m| m.t_pos == mv.t_pos && m.f_pos != mv.f_pos && self.at(m.f_pos).is_some_and(|t| t.piece == piece))
+					.map(|m| m.f_pos)
+					.collect();
+				if !others.is_empty() {
+					let same_file = others.iter().any(|p| p.col == mv.f_pos.col);
+					let same_rank = others.iter().any(|p| p.row == mv.f_pos.row);
+					if !same_file {
+						san.push(file(mv.f_pos));
+					} else if !same_rank {
+						san.push((b'1' + mv.f_pos.row as u8) as char);
+					} else {
+						san.push_str(&mv.f_pos.to_string());
+					}
+				}
+			}
+			if capture {
+				san.push('x');
+			}
+			san.push_str(&mv.t_pos.to_string());
+			if piece == Piece::Pawn && (mv.t_pos.row == 0 || mv.t_pos.row == 7) {
+				san.push_str("=Q");
+			}
+		}
+		let after = self.clone_apply_move(mv);
+		if after.is_king_in_check(after.player) {
+			san.push(if after.generate_all().is_empty() { '#' } else { '+' });
+		}
+		san
+	}
+
+	/// Writes a sequence of moves from this position, such as a principal variation, in algebraic notation
+	pub fn line_to_san(&self, moves: &[Move]) -> Vec<String> {
+		let mut b = self.clone();
+		moves.iter().map(|mv| {
+			let san = b.move_to_san(mv);
+			b = b.clone_apply_move(mv);
+			san
+		}).collect()
+	}
+
+	/// Finds the legal move written in algebraic notation
+	pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+		let san = san.trim_end_matches(['+', '#', '!', '?']);
+		let legal = self.generate_all();
+
+		/* Castling */
+		let castle_col = match san {
+			"O-O" | "0-0" => Some(6),
+			"O-O-O" | "0-0-0" => Some(2),
+			_ => None,
+		};
+		if let Some(col) = castle_col {
+			let king = self.king_pos[self.player as usize];
+			return legal.iter()
+				.map(|(mv, _)| *mv)
+				.find(|mv| mv.f_pos == king && mv.t_pos.col == col && (mv.f_pos.col - col).abs() == 2)
+				.ok_or(SanError::Illegal);
+		}
+
+		let mut chars: Vec<char> = san.chars().filter(|c| *c != 'x' && *c != '-' && *c != ':').collect();
+		let piece = match chars.first() {
+			Some(c) if c.is_ascii_uppercase() => {
+				let p = Piece::from_char(*c).map_err(|_| SanError::Syntax)?;
+				chars.remove(0);
+				p
+			},
+			Some(_) => Piece::Pawn,
+			None => return Err(SanError::Syntax),
+		};
+
+		/* Promotion, with or without `=` */
+		if piece == Piece::Pawn && chars.last().is_some_and(|c| c.is_ascii_uppercase()) {
+			if chars.pop() != Some('Q') {
+				return Err(SanError::Underpromotion);
+			}
+			if chars.last() == Some(&'=') {
+				chars.pop();
+			}
+		}
+
+		if chars.len() < 2 {
+			return Err(SanError::Syntax);
+		}
+		let dest: String = chars[chars.len() - 2..].iter().collect();
+		let t_pos = Pos::from_coord(&dest).ok_or(SanError::Syntax)?;
+		/* Disambiguation: origin file, rank or both */
+		let mut from_col = None;
+		let mut from_row = None;
+		for c in &chars[..chars.len() - 2] {
+			match c {
+				'a'..='h' => from_col = Some(*c as i8 - 'a' as i8),
+				'1'..='8' => from_row = Some(*c as i8 - '1' as i8),
+				_ => return Err(SanError::Syntax),
+			}
+		}
+
+		let mut candidates = legal.iter().map(|(mv, _)| *mv).filter(|mv| {
+			mv.t_pos == t_pos
+				&& self.at(mv.f_pos).is_some_and(|t| t.piece == piece)
+				&& from_col.is_none_or(|c| mv.f_pos.col == c)
+				&& from_row.is_none_or(|r| mv.f_pos.row == r)
+		});
+		match (candidates.next(), candidates.next()) {
+			(Some(mv), None) => Ok(mv),
+			(Some(_), Some(_)) => Err(SanError::Ambiguous),
+			(None, _) => Err(SanError::Illegal),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::board::{Board,Color,Move};
+	use crate::san::SanError;
+
+	#[test]
+	pub fn test_parse_san() {
+		let b = Board::new(Color::White);
+		debug_assert_eq!(b.parse_san("e4"), Ok("e2e4".parse::<Move>().unwrap()));
+		debug_assert_eq!(b.parse_san("Nf3"), Ok("g1f3".parse::<Move>().unwrap()));
+		debug_assert_eq!(b.parse_san("e5"), Err(SanError::Illegal));
+		debug_assert_eq!(b.parse_san("Zz9"), Err(SanError::Syntax));
+		let b = Board::from_fen("r3k2r/1P6/8/8/8/5p2/4P3/RN2K2R w KQkq - 0 1").unwrap();
+		debug_assert_eq!(b.parse_san("O-O+"), Ok("e1g1".parse::<Move>().unwrap()));
+		debug_assert_eq!(b.parse_san("exf3"), Ok("e2f3".parse::<Move>().unwrap()));
+		debug_assert_eq!(b.parse_san("bxa8=Q"), Ok("b7a8".parse::<Move>().unwrap()));
+		debug_assert_eq!(b.parse_san("b8=N"), Err(SanError::Underpromotion));
+		let b = Board::from_fen("4k3/8/8/8/8/8/4K3/R6R w - - 0 1").unwrap();
+		debug_assert_eq!(b.parse_san("Rd1"), Err(SanError::Ambiguous));
+		debug_assert_eq!(b.parse_san("Rad1"), Ok("a1d1".parse::<Move>().unwrap()));
+	}
+
+	#[test]
+	pub fn test_move_to_san() {
+		let b = Board::new(Color::White);
+		let san = |b: &Board, mv: &str| b.move_to_san(&mv.parse::<Move>().unwrap());
+		debug_assert_eq!(san(&b, "e2e4"), "e4");
+		debug_assert_eq!(san(&b, "g1f3"), "Nf3");
+		debug_assert_eq!(b.line_to_san(&["f2f3", "e7e5", "g2g4", "d8h4"].map(|m| m.parse::<Move>().unwrap())), vec!["f3", "e5", "g4", "Qh4#"]);
+		let b = Board::from_fen("r3k2r/1P6/8/8/8/5p2/4P3/RN2K2R w KQkq - 0 1").unwrap();
+		debug_assert_eq!(san(&b, "e1g1"), "O-O");
+		debug_assert_eq!(san(&b, "e2f3"), "exf3");
+		debug_assert_eq!(san(&b, "b7a8"), "bxa8=Q+");
+		debug_assert_eq!(san(&b, "b1c3"), "Nc3");
+		let b = Board::from_fen("4k3/8/8/8/R7/8/4K3/R6R w - - 0 1").unwrap();
+		debug_assert_eq!(san(&b, "a1d1"), "Rad1");
+		debug_assert_eq!(san(&b, "a1a3"), "R1a3");
+		debug_assert_eq!(san(&b, "a4a3"), "R4a3");
+		let b = Board::from_fen("k7/8/8/8/8/2Q1Q3/8/2Q1K3 w - - 0 1").unwrap();
+		debug_assert_eq!(san(&b, "c3d2"), "Qc3d2");
+		// round trip through the parser
+		for (mv, _) in b.generate_all() {
+			debug_assert_eq!(b.parse_san(&b.move_to_san(&mv)), Ok(mv));
+		}
+	}
+}
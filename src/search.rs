@@ -0,0 +1,296 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::board::{Board, Color, Move};
+use crate::evaluation::{Evaluator, Value, new_evaluator};
+use crate::misc::*;
+use crate::minimax::Minimax;
+use crate::negamax::Negamax;
+use crate::negascout::Negascout;
+use crate::tt::TranspositionTable;
+
+/* How often (in nodes) the clock is looked at */
+const CHECK_TIME_EVERY: u64 = 1024;
+
+/// Constraints on a single search
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SearchLimits {
+	pub max_depth: u8,
+	/// Time budget, in milliseconds
+	pub movetime_ms: Option<u64>,
+	pub nodes: Option<u64>,
+}
+impl SearchLimits {
+	pub fn new(opts: &Options) -> Self {
+		SearchLimits {
+			max_depth: opts.max_depth,
+			movetime_ms: None,
+			nodes: None,
+		}
+	}
+}
+
+/// Lets other threads steer a running search
+#[derive(Debug, Default)]
+pub struct SearchControl {
+	/// Raised from outside (e.g. UCI `stop`) to end the search as soon as possible
+	pub abort: AtomicBool,
+	/// While raised, the engine thinks on the opponent's time and time limits don't apply.
+	/// Once lowered (UCI `ponderhit`), the time budget starts running.
+	pub pondering: AtomicBool,
+}
+
+/// State shared by all search algorithms
+#[derive(Debug)]
+pub struct SearchState {
+	pub opts: Options,
+	pub limits: SearchLimits,
+	pub info: SearchInfo,
+	pub control: Arc<SearchControl>,
+	/// Shared with the helper threads
+	pub tt: Arc<TranspositionTable>,
+	/// Static evaluation at the leaves, shared with the helper threads
+	pub eval: Arc<dyn Evaluator>,
+	/// Root moves not to be searched (those already reported in MultiPV mode)
+	pub excluded: Vec<Move>,
+	stopwatch: Stopwatch,
+	stopped: bool,
+	pondering: bool,
+}
+impl SearchState {
+	pub fn new(opts: &Options) -> Self {
+		SearchState {
+			opts: opts.clone(),
+			limits: SearchLimits::new(opts),
+			info: SearchInfo::new(),
+			control: Arc::new(SearchControl::default()),
+			tt: Arc::new(TranspositionTable::new(opts.hash_mb)),
+			eval: new_evaluator(opts),
+			excluded: Vec::new(),
+			stopwatch: Stopwatch::start(),
+			stopped: false,
+			pondering: false,
+		}
+	}
+
+	/// State for a helper thread of a parallel search: it shares the
+	/// transposition table, has no time or node limits and is stopped through `control`
+	#[allow(dead_code)]
+	pub fn helper(&self, control: Arc<SearchControl>) -> Self {
+		SearchState {
+			opts: self.opts.clone(),
+			limits: SearchLimits { movetime_ms: None, nodes: None, ..self.limits },
+			info: SearchInfo::new(),
+			control,
+			tt: self.tt.clone(),
+			eval: self.eval.clone(),
+			excluded: self.excluded.clone(),
+			stopwatch: Stopwatch::start(),
+			stopped: false,
+			pondering: false,
+		}
+	}
+
+	/// Prepares the state for a new search
+	pub fn start(&mut self) {
+		self.info = SearchInfo::new();
+		self.stopwatch = Stopwatch::start();
+		self.stopped = false;
+		self.pondering = self.control.pondering.load(Ordering::Relaxed);
+	}
+
+	pub fn elapsed_ms(&self) -> u64 {
+		self.stopwatch.elapsed_ms()
+	}
+
+	/// Counts a visited node and tells whether the search must stop
+	pub fn visit(&mut self) -> bool {
+		self.info.nodes += 1;
+		if !self.stopped {
+			self.stopped = self.control.abort.load(Ordering::Relaxed)
+				|| self.limits.nodes.is_some_and(|n| self.info.nodes > n)
+				|| (self.info.nodes.is_multiple_of(CHECK_TIME_EVERY) && self.out_of_time());
+		}
+		self.stopped
+	}
+
+	fn out_of_time(&mut self) -> bool {
+		if self.pondering {
+			if self.control.pondering.load(Ordering::Relaxed) {
+				return false;
+			}
+			// ponderhit: from now on this is a normal timed search
+			self.pondering = false;
+			self.stopwatch = Stopwatch::start();
+		}
+		self.limits.movetime_ms.is_some_and(|t| self.elapsed_ms() >= t)
+	}
+
+	/// Wraps up a search. `score` is from White's point of view.
+	/// If the search was interrupted before any root move was completed,
+	/// falls back to the first legal move.
+	pub fn result(&self, b: &Board, score: Value, mut pv: Vec<Move>) -> Option<SearchResult> {
+		if pv.is_empty() {
+			pv.push(b.generate_all().into_iter().map(|(mv, _)| mv).find(|mv| !self.excluded.contains(mv))?);
+		}
+		Some(SearchResult {
+			score,
+			best_move: pv[0],
+			pv: pv.clone(),
+			depth: self.info.depth,
+			nodes: self.info.nodes,
+			time_ms: self.elapsed_ms(),
+			researches: self.info.researches,
+			lines: vec![PvLine { score, pv }],
+		})
+	}
+
+	/// Removes the excluded moves from the root moves
+	pub fn exclude_root_moves(&self, bs: &mut Vec<(Move, Board)>) {
+		if !self.excluded.is_empty() {
+			bs.retain(|(mv, _)| !self.excluded.contains(mv));
+		}
+	}
+
+	/// Whether the search was interrupted before completing
+	#[inline]
+	pub fn stopped(&self) -> bool {
+		self.stopped
+	}
+}
+
+/// A search algorithm
+pub trait Searcher {
+	fn state_mut(&mut self) -> &mut SearchState;
+
+	/// Searches the best root move, skipping the excluded ones
+	fn search_root(&mut self, b: &Board) -> Option<SearchResult>;
+
+	/// Searches the given board within the current limits.
+	/// In MultiPV mode the root is searched once per line, each time
+	/// excluding the root moves already found.
+	fn search(&mut self, b: &Board) -> Option<SearchResult> {
+		let state = self.state_mut();
+		state.start();
+		state.excluded.clear();
+		let multi_pv = state.opts.multi_pv.max(1);
+		if state.opts.own_book {
+			let book_move = state.opts.book.as_ref().and_then(|book| book.pick(b, state.opts.book_random));
+			if let Some(mv) = book_move {
+				// no search: the score is unknown
+				return state.result(b, 0, vec![mv]);
+			}
+		}
+		if let Some((mv, wdl)) = state.opts.syzygy.as_ref().and_then(|tb| tb.root_move(b)) {
+			// no search either: the tables know better
+			let score = wdl.value(0);
+			return state.result(b, if b.player == Color::White { score } else { -score }, vec![mv]);
+		}
+		let mut root = b.clone();
+		state.eval.clone().attach(&mut root);
+		let b = &root;
+
+		let mut best: Option<SearchResult> = None;
+		let mut lines = Vec::new();
+		while lines.len() < multi_pv {
+			let res = match self.search_root(b) {
+				Some(res) => res,
+				None => break,
+			};
+			let state = self.state_mut();
+			if state.stopped() && best.is_some() {
+				// this line is incomplete
+				break;
+			}
+			state.excluded.push(res.best_move);
+			lines.push(PvLine { score: res.score, pv: res.pv.clone() });
+			let stopped = state.stopped();
+			best = Some(match best {
+				None => res,
+				Some(first) => SearchResult { nodes: res.nodes, time_ms: res.time_ms, researches: res.researches, ..first },
+			});
+			if stopped {
+				break;
+			}
+		}
+		self.state_mut().excluded.clear();
+
+		let mut best = best?;
+		let white = b.player == Color::White;
+		lines.sort_by_key(|l| if white { -l.score } else { l.score });
+		best.score = lines[0].score;
+		best.pv = lines[0].pv.clone();
+		best.best_move = best.pv[0];
+		best.lines = lines;
+		Some(best)
+	}
+}
+
+/// Creates the searcher for the algorithm selected in the options
+pub fn new_searcher(opts: &Options) -> Box<dyn Searcher + Send> {
+	match opts.search_algo {
+		SearchAlgorithm::Minimax => Box::new(Minimax::new(opts)),
+		SearchAlgorithm::Negamax => Box::new(Negamax::new(opts)),
+		SearchAlgorithm::Negascout => Box::new(Negascout::new(opts)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::Ordering;
+	use std::thread;
+	use std::time::Duration;
+	use crate::board::{Board,Color};
+	use crate::misc::{OPTS_DEFAULT,SearchAlgorithm};
+	use crate::search::{SearchState,new_searcher};
+
+	#[test]
+	pub fn test_multi_pv() {
+		for algo in [SearchAlgorithm::Minimax, SearchAlgorithm::Negamax, SearchAlgorithm::Negascout] {
+			let mut opts = OPTS_DEFAULT.clone();
+			opts.search_algo = algo;
+			opts.max_depth = 3;
+			opts.multi_pv = 3;
+			let res = new_searcher(&opts).search(&Board::new(Color::White)).expect("a move");
+			debug_assert_eq!(res.lines.len(), 3);
+			debug_assert_eq!(res.lines[0].pv, res.pv);
+			debug_assert_ne!(res.lines[0].pv[0], res.lines[1].pv[0]);
+			debug_assert_ne!(res.lines[1].pv[0], res.lines[2].pv[0]);
+			debug_assert!(res.lines[0].score >= res.lines[1].score && res.lines[1].score >= res.lines[2].score);
+		}
+	}
+
+	#[test]
+	pub fn test_interrupted_search() {
+		let b = Board::new(Color::White);
+		for algo in [SearchAlgorithm::Minimax, SearchAlgorithm::Negamax, SearchAlgorithm::Negascout] {
+			let mut opts = OPTS_DEFAULT.clone();
+			opts.search_algo = algo;
+			opts.max_depth = 6;
+			let mut searcher = new_searcher(&opts);
+			searcher.state_mut().limits.nodes = Some(3000);
+			let res = searcher.search(&b).expect("a move");
+			// the last completed iteration
+			debug_assert!(res.depth >= 1 && res.depth < 6);
+			opts.max_depth = res.depth;
+			let full = new_searcher(&opts).search(&b).expect("a move");
+			debug_assert_eq!((res.best_move, res.score), (full.best_move, full.score));
+		}
+	}
+
+	#[test]
+	pub fn test_pondering_clock() {
+		let mut state = SearchState::new(&OPTS_DEFAULT);
+		state.limits.movetime_ms = Some(20);
+		state.control.pondering.store(true, Ordering::Relaxed);
+		state.start();
+		// no time limit while pondering
+		thread::sleep(Duration::from_millis(30));
+		debug_assert!(!state.out_of_time());
+		// ponderhit: the clock starts now
+		state.control.pondering.store(false, Ordering::Relaxed);
+		debug_assert!(!state.out_of_time());
+		thread::sleep(Duration::from_millis(30));
+		debug_assert!(state.out_of_time());
+	}
+}
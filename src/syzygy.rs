@@ -0,0 +1,1192 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::ops;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::board::{Board, Color, Move, Piece};
+use crate::evaluation::{Value, MATE, MAX_PLY};
+
+/*
+ * Syzygy endgame tablebases: https://www.chessprogramming.org/Syzygy_Bases
+ * WDL tables (.rtbw) tell whether a position is won, drawn or lost. DTZ tables (.rtbz)
+ * give the distance to the next zeroing move (capture or pawn move), which is what
+ * is needed to make progress towards the win without running into the 50-move rule.
+ *
+ * Decoding follows the reference probing code by Ronald de Man, as found in Stockfish and Fathom.
+ * Tables are read whole into memory the first time they are probed.
+ * The board keeps neither an en passant square nor the halfmove clock: positions are probed
+ * as if no en passant capture was possible and as if the last move had been a zeroing one.
+ * Positions with castling rights are not in the tables.
+*/
+
+const TB_PIECES: usize = 7;
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+const WDL_SUFFIX: &str = ".rtbw";
+const DTZ_SUFFIX: &str = ".rtbz";
+
+/* Flags of each sub-table */
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/* Root moves are ranked by DTZ: wins within the 50-move rule all rank MAX_DTZ */
+const MAX_DTZ: i32 = 1 << 18;
+
+/// Score of a tablebase win at the root: below the mate scores, above any evaluation
+pub const TB_WIN: Value = MATE - 2 * MAX_PLY;
+
+/// Game theoretical value for the side to move. Cursed wins and blessed losses
+/// are wins and losses that the 50-move rule turns into draws
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+	Loss = -2,
+	BlessedLoss = -1,
+	Draw = 0,
+	CursedWin = 1,
+	Win = 2,
+}
+
+impl Wdl {
+	fn from_i32(v: i32) -> Wdl {
+		match v {
+			i32::MIN..=-2 => Wdl::Loss,
+			-1 => Wdl::BlessedLoss,
+			0 => Wdl::Draw,
+			1 => Wdl::CursedWin,
+			_ => Wdl::Win,
+		}
+	}
+
+	/// Score for the side to move, `ply` plies away from the root
+	pub fn value(self, ply: u8) -> Value {
+		match self {
+			Wdl::Win => TB_WIN - ply as Value,
+			Wdl::CursedWin => 2,
+			Wdl::Draw => 0,
+			Wdl::BlessedLoss => -2,
+			Wdl::Loss => -TB_WIN + ply as Value,
+		}
+	}
+
+	fn sign(self) -> i32 {
+		(self as i32).signum()
+	}
+}
+
+impl ops::Neg for Wdl {
+	type Output = Wdl;
+
+	fn neg(self) -> Wdl {
+		Wdl::from_i32(-(self as i32))
+	}
+}
+
+/// DTZ of the move before a zeroing move, which the DTZ tables don't store
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+	match wdl {
+		Wdl::Win => 1,
+		Wdl::CursedWin => 101,
+		Wdl::Draw => 0,
+		Wdl::BlessedLoss => -101,
+		Wdl::Loss => -1,
+	}
+}
+
+/* Squares are numbered from a1 = 0 to h8 = 63, as in the tables */
+#[inline]
+fn rank_of(sq: usize) -> usize {
+	sq >> 3
+}
+
+#[inline]
+fn file_of(sq: usize) -> usize {
+	sq & 7
+}
+
+/// Distance above the a1-h8 diagonal: positive above, negative below
+#[inline]
+fn off_a1h8(sq: usize) -> i32 {
+	rank_of(sq) as i32 - file_of(sq) as i32
+}
+
+/// Tables used to turn positions into indices
+struct Indexing {
+	/// a2-h7 to 0..47, larger towards the edges and lower ranks: the leading pawn has the largest
+	map_pawns: [usize; 64],
+	/// Squares below the a1-h8 diagonal to 0..27
+	map_b1h1h7: [usize; 64],
+	/// Squares of the a1-d1-d4 triangle to 0..9, diagonal last
+	map_a1d1d4: [usize; 64],
+	/// The 462 legal placements of two kings, the first in the a1-d1-d4 triangle
+	map_kk: [[usize; 64]; 10],
+	/// `binomial[k][n]`: ways of choosing k among n
+	binomial: [[u64; 64]; TB_PIECES],
+	/// Index of the leading pawns group by number of leading pawns and square of the first one
+	lead_pawn_idx: [[u64; 64]; 6],
+	/// Number of placements of the leading pawns group by number of leading pawns and file
+	lead_pawns_size: [[u64; 4]; 6],
+}
+
+impl Indexing {
+	fn new() -> Self {
+		let mut ix = Indexing {
+			map_pawns: [0; 64],
+			map_b1h1h7: [0; 64],
+			map_a1d1d4: [0; 64],
+			map_kk: [[0; 64]; 10],
+			binomial: [[0; 64]; TB_PIECES],
+			lead_pawn_idx: [[0; 64]; 6],
+			lead_pawns_size: [[0; 4]; 6],
+		};
+
+		let mut code = 0;
+		for sq in 0..64 {
+			if off_a1h8(sq) < 0 {
+				ix.map_b1h1h7[sq] = code;
+				code += 1;
+			}
+		}
+
+		let mut diagonal = Vec::new();
+		code = 0;
+		for sq in 0..=27 {
+			if off_a1h8(sq) < 0 && file_of(sq) <= 3 {
+				ix.map_a1d1d4[sq] = code;
+				code += 1;
+			} else if off_a1h8(sq) == 0 && file_of(sq) <= 3 {
+				diagonal.push(sq);
+			}
+		}
+		for sq in diagonal {
+			ix.map_a1d1d4[sq] = code;
+			code += 1;
+		}
+
+		let adjacent = |a: usize, b: usize| {
+			(rank_of(a) as i32 - rank_of(b) as i32).abs() <= 1 && (file_of(a) as i32 - file_of(b) as i32).abs() <= 1
+		};
+		let mut both_on_diagonal = Vec::new();
+		code = 0;
+		for idx in 0..10 {
+			for s1 in 0..=27 {
+				// b1 is mapped to 0, like the squares outside the triangle
+				if ix.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+					continue;
+				}
+				for s2 in 0..64 {
+					if adjacent(s1, s2) || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+						continue;
+					}
+					if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+						both_on_diagonal.push((idx, s2));
+					} else {
+						ix.map_kk[idx][s2] = code;
+						code += 1;
+					}
+				}
+			}
+		}
+		for (idx, s2) in both_on_diagonal {
+			ix.map_kk[idx][s2] = code;
+			code += 1;
+		}
+
+		ix.binomial[0][0] = 1;
+		for n in 1..64 {
+			for k in 0..TB_PIECES.min(n + 1) {
+				ix.binomial[k][n] = if k > 0 { ix.binomial[k - 1][n - 1] } else { 0 }
+					+ if k < n { ix.binomial[k][n - 1] } else { 0 };
+			}
+		}
+
+		let mut available = 47;
+		for lead_pawns in 1..=5 {
+			for file in 0..4 {
+				let mut idx = 0;
+				for rank in 1..7 {
+					let sq = rank * 8 + file;
+					if lead_pawns == 1 {
+						ix.map_pawns[sq] = available;
+						ix.map_pawns[sq ^ 7] = available - 1;
+						available = available.saturating_sub(2);
+					}
+					ix.lead_pawn_idx[lead_pawns][sq] = idx;
+					idx += ix.binomial[lead_pawns - 1][ix.map_pawns[sq]];
+				}
+				ix.lead_pawns_size[lead_pawns][file] = idx;
+			}
+		}
+		ix
+	}
+}
+
+fn indexing() -> &'static Indexing {
+	static INDEXING: OnceLock<Indexing> = OnceLock::new();
+	INDEXING.get_or_init(Indexing::new)
+}
+
+/* Little and big endian reads, failing past the end of the file */
+fn u8_at(bytes: &[u8], off: usize) -> Result<u8, String> {
+	bytes.get(off).copied().ok_or_else(|| String::from("truncated file"))
+}
+
+fn u16_le(bytes: &[u8], off: usize) -> Result<u16, String> {
+	Ok(u16::from_le_bytes([u8_at(bytes, off)?, u8_at(bytes, off + 1)?]))
+}
+
+fn u32_le(bytes: &[u8], off: usize) -> Result<u32, String> {
+	Ok(u32::from_le_bytes([u8_at(bytes, off)?, u8_at(bytes, off + 1)?, u8_at(bytes, off + 2)?, u8_at(bytes, off + 3)?]))
+}
+
+fn u32_be(bytes: &[u8], off: usize) -> Option<u32> {
+	let b = bytes.get(off..off + 4)?;
+	Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Decoding data of a table for one side to move and, with pawns, one file of the leading pawn
+#[derive(Debug, Default, Clone)]
+struct PairsData {
+	flags: u8,
+	max_sym_len: u8,
+	/// The value of the whole table with `FLAG_SINGLE_VALUE`
+	min_sym_len: u8,
+	num_blocks: usize,
+	block_size: usize,
+	/// There is a sparse index entry about every `span` values
+	span: usize,
+	/// Offsets in the file of the lowest symbol of each length, the pairs of each symbol,
+	/// the sparse index, the block lengths and the compressed data
+	lowest_sym: usize,
+	btree: usize,
+	sparse_index: usize,
+	sparse_index_size: usize,
+	block_length: usize,
+	block_length_size: usize,
+	data: usize,
+	/// 64-bit left-aligned lowest code of each symbol length
+	base64: Vec<u64>,
+	/// Number of values (minus one) each symbol stands for
+	symlen: Vec<u8>,
+	/// Pieces in encoding order, as table codes: 1..6 for white pawn..king, 9..14 for black
+	pieces: [u8; TB_PIECES],
+	group_idx: [u64; TB_PIECES + 1],
+	/// Sizes of the groups of pieces encoded together, zero-terminated
+	group_len: [usize; TB_PIECES + 1],
+	/// DTZ only: offsets of the value maps for wins, losses, cursed wins and blessed losses
+	map_idx: [usize; 4],
+}
+
+/// A WDL or DTZ table file
+struct Table {
+	bytes: Vec<u8>,
+	dtz: bool,
+	/// Same material on both sides, e.g. KRvKR: only stored for White to move
+	symmetric: bool,
+	piece_count: usize,
+	has_pawns: bool,
+	has_unique_pieces: bool,
+	/// Pawns of the leading color (the one with fewer pawns, if any), then of the other
+	pawn_count: [usize; 2],
+	/// By side to move, then file of the leading pawn (a to d) or 0 without pawns
+	items: Vec<Vec<PairsData>>,
+}
+
+/// What was found in a table
+enum TableValue {
+	Value(i32),
+	/// DTZ tables only store one side to move: this is the other one
+	ChangeStm,
+}
+
+impl Table {
+	/// Reads the file of the table with White's pieces `white` and Black's `black`, e.g. "KQ" and "K"
+	fn parse(bytes: Vec<u8>, dtz: bool, white: &str, black: &str) -> Result<Table, String> {
+		let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+		if bytes.len() < 5 || bytes[..4] != magic {
+			return Err(String::from("not a Syzygy table"));
+		}
+		let count = |side: &str, p: char| side.chars().filter(|c| *c == p).count();
+		let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+		// the leading color has fewer pawns, as long as it has some
+		let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+		let mut table = Table {
+			bytes: Vec::new(),
+			dtz,
+			symmetric: white == black,
+			piece_count: white.len() + black.len(),
+			has_pawns: white_pawns + black_pawns > 0,
+			has_unique_pieces: "QRBN".chars().any(|p| count(white, p) == 1 || count(black, p) == 1)
+				|| white_pawns == 1 || black_pawns == 1,
+			pawn_count: if white_leads { [white_pawns, black_pawns] } else { [black_pawns, white_pawns] },
+			items: Vec::new(),
+		};
+		if table.piece_count > TB_PIECES {
+			return Err(format!("more than {} pieces", TB_PIECES));
+		}
+		let flags = bytes[4];
+		if (flags & 2 != 0) != table.has_pawns || (flags & 1 != 0) == table.symmetric {
+			return Err(String::from("the table doesn't match its name"));
+		}
+
+		let sides = if !dtz && !table.symmetric { 2 } else { 1 };
+		let files = if table.has_pawns { 4 } else { 1 };
+		// pawns on both sides
+		let pp = table.has_pawns && table.pawn_count[1] > 0;
+		table.items = vec![vec![PairsData::default(); files]; sides];
+
+		let mut data = 5;
+		for f in 0..files {
+			let order_byte = u8_at(&bytes, data)?;
+			let pp_byte = if pp { u8_at(&bytes, data + 1)? } else { 0xFF };
+			let order = [[order_byte & 0xF, pp_byte & 0xF], [order_byte >> 4, pp_byte >> 4]];
+			data += 1 + pp as usize;
+			for k in 0..table.piece_count {
+				let b = u8_at(&bytes, data)?;
+				for i in 0..sides {
+					table.items[i][f].pieces[k] = if i == 1 { b >> 4 } else { b & 0xF };
+				}
+				data += 1;
+			}
+			for (i, order) in order.iter().enumerate().take(sides) {
+				let mut d = std::mem::take(&mut table.items[i][f]);
+				table.set_groups(&mut d, *order, f);
+				table.items[i][f] = d;
+			}
+		}
+		data += data & 1;
+
+		for f in 0..files {
+			for i in 0..sides {
+				data = set_sizes(&mut table.items[i][f], &bytes, data)?;
+			}
+		}
+
+		if dtz {
+			for f in 0..files {
+				let d = &mut table.items[0][f];
+				if d.flags & FLAG_MAPPED == 0 {
+					continue;
+				}
+				for i in 0..4 {
+					if d.flags & FLAG_WIDE != 0 {
+						data += data & 1;
+						d.map_idx[i] = data + 2;
+						data += 2 * u16_le(&bytes, data)? as usize + 2;
+					} else {
+						d.map_idx[i] = data + 1;
+						data += u8_at(&bytes, data)? as usize + 1;
+					}
+				}
+			}
+			data += data & 1;
+		}
+
+		for f in 0..files {
+			for i in 0..sides {
+				let d = &mut table.items[i][f];
+				d.sparse_index = data;
+				data += d.sparse_index_size * 6;
+			}
+		}
+		for f in 0..files {
+			for i in 0..sides {
+				let d = &mut table.items[i][f];
+				d.block_length = data;
+				data += d.block_length_size * 2;
+			}
+		}
+		for f in 0..files {
+			for i in 0..sides {
+				let d = &mut table.items[i][f];
+				data = (data + 0x3F) & !0x3F;
+				d.data = data;
+				data += d.num_blocks * d.block_size;
+			}
+		}
+		if data > bytes.len() {
+			return Err(String::from("truncated file"));
+		}
+		table.bytes = bytes;
+		Ok(table)
+	}
+
+	/// Splits the pieces in groups encoded together and computes the index factor of each group
+	fn set_groups(&self, d: &mut PairsData, order: [u8; 2], file: usize) {
+		let ix = indexing();
+		let mut n = 0;
+		let mut first_len: i32 = if self.has_pawns { 0 } else if self.has_unique_pieces { 3 } else { 2 };
+		d.group_len[0] = 1;
+		for i in 1..self.piece_count {
+			first_len -= 1;
+			if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+				d.group_len[n] += 1;
+			} else {
+				n += 1;
+				d.group_len[n] = 1;
+			}
+		}
+		n += 1;
+		d.group_len[n] = 0;
+
+		// the groups are not necessarily encoded in order
+		let pp = self.has_pawns && self.pawn_count[1] > 0;
+		let mut next = if pp { 2 } else { 1 };
+		let mut free_squares = 64 - d.group_len[0] - if pp { d.group_len[1] } else { 0 };
+		let mut idx: u64 = 1;
+		let mut k = 0;
+		while next < n || k == order[0] as usize || k == order[1] as usize {
+			if k == order[0] as usize {
+				// leading pawns or pieces
+				d.group_idx[0] = idx;
+				idx *= if self.has_pawns {
+					ix.lead_pawns_size[d.group_len[0]][file]
+				} else if self.has_unique_pieces {
+					31332
+				} else {
+					462
+				};
+			} else if k == order[1] as usize {
+				// remaining pawns
+				d.group_idx[1] = idx;
+				idx *= ix.binomial[d.group_len[1]][48 - d.group_len[0]];
+			} else {
+				d.group_idx[next] = idx;
+				idx *= ix.binomial[d.group_len[next]][free_squares];
+				free_squares -= d.group_len[next];
+				next += 1;
+			}
+			k += 1;
+		}
+		d.group_idx[n] = idx;
+	}
+
+	fn get(&self, stm: usize, file: usize) -> &PairsData {
+		&self.items[stm % self.items.len()][if self.has_pawns { file } else { 0 }]
+	}
+
+	/// Left and right symbols that `sym` expands to
+	fn pair(&self, d: &PairsData, sym: usize) -> Option<(usize, usize)> {
+		let lr = self.bytes.get(d.btree + 3 * sym..d.btree + 3 * sym + 3)?;
+		let left = ((lr[1] as usize & 0xF) << 8) | lr[0] as usize;
+		let right = ((lr[2] as usize) << 4) | (lr[1] as usize >> 4);
+		Some((left, right))
+	}
+
+	/// Value number `idx` of the sub-table: the data is split in blocks of Huffman coded
+	/// symbols, each standing for a pair of symbols or (at the leaves) a value
+	fn decompress_pairs(&self, d: &PairsData, idx: u64) -> Option<i32> {
+		if d.flags & FLAG_SINGLE_VALUE != 0 {
+			return Some(d.min_sym_len as i32);
+		}
+		let bytes = &self.bytes;
+		let block_length = |block: usize| -> Option<i64> {
+			Some(u16_le(bytes, d.block_length + 2 * block).ok()? as i64)
+		};
+
+		// the sparse index points to the block of value `k * span + span / 2`
+		let k = (idx / d.span as u64) as usize;
+		let mut block = u32_le(bytes, d.sparse_index + 6 * k).ok()? as usize;
+		let mut offset = u16_le(bytes, d.sparse_index + 6 * k + 4).ok()? as i64;
+		offset += (idx % d.span as u64) as i64 - (d.span / 2) as i64;
+		while offset < 0 {
+			block = block.checked_sub(1)?;
+			offset += block_length(block)? + 1;
+		}
+		while offset > block_length(block)? {
+			offset -= block_length(block)? + 1;
+			block += 1;
+		}
+
+		let mut ptr = d.data + block * d.block_size;
+		let mut buf64 = ((u32_be(bytes, ptr)? as u64) << 32) | u32_be(bytes, ptr + 4)? as u64;
+		ptr += 8;
+		let mut buf64_size = 64;
+		let min_len = d.min_sym_len as usize;
+		let mut sym;
+		loop {
+			// codes of the same length are consecutive, longer codes are lower
+			let mut len = 0;
+			while buf64 < *d.base64.get(len)? {
+				len += 1;
+			}
+			let lowest = u16_le(bytes, d.lowest_sym + 2 * len).ok()? as usize;
+			sym = ((buf64 - d.base64[len]) >> (64 - len - min_len)) as usize + lowest;
+			let count = *d.symlen.get(sym)? as i64 + 1;
+			if offset < count {
+				break;
+			}
+			offset -= count;
+			len += min_len;
+			buf64 <<= len;
+			buf64_size -= len;
+			if buf64_size <= 32 {
+				buf64_size += 32;
+				buf64 |= (u32_be(bytes, ptr)? as u64) << (64 - buf64_size);
+				ptr += 4;
+			}
+		}
+
+		// expand the pairs down to the value
+		while *d.symlen.get(sym)? != 0 {
+			let (left, right) = self.pair(d, sym)?;
+			let count = *d.symlen.get(left)? as i64 + 1;
+			if offset < count {
+				sym = left;
+			} else {
+				offset -= count;
+				sym = right;
+			}
+		}
+		Some(self.pair(d, sym)?.0 as i32)
+	}
+
+	/// Converts a DTZ table value to plies
+	fn map_dtz(&self, file: usize, value: i32, wdl: Wdl) -> Option<i32> {
+		const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+		let d = self.get(0, file);
+		let mut value = value;
+		if d.flags & FLAG_MAPPED != 0 {
+			let start = d.map_idx[WDL_MAP[(wdl as i32 + 2) as usize]];
+			value = if d.flags & FLAG_WIDE != 0 {
+				u16_le(&self.bytes, start + 2 * value as usize).ok()? as i32
+			} else {
+				u8_at(&self.bytes, start + value as usize).ok()? as i32
+			};
+		}
+		// stored in moves rather than plies, unless flagged
+		if (wdl == Wdl::Win && d.flags & FLAG_WIN_PLIES == 0)
+			|| (wdl == Wdl::Loss && d.flags & FLAG_LOSS_PLIES == 0)
+			|| wdl == Wdl::CursedWin
+			|| wdl == Wdl::BlessedLoss {
+			value *= 2;
+		}
+		Some(value + 1)
+	}
+
+	/// Looks the position up. `flipped` when the table has Black's material as White's,
+	/// or is symmetric and Black is to move: colors and ranks are then swapped
+	fn probe(&self, b: &Board, flipped: bool, wdl: Wdl) -> Option<TableValue> {
+		let ix = indexing();
+		let flip_color = if flipped { 8 } else { 0 };
+		let flip_squares = if flipped { 56 } else { 0 };
+		let stm = (flipped as usize) ^ (b.player == Color::Black) as usize;
+
+		// all pieces, as (square, table code)
+		let mut all = Vec::with_capacity(TB_PIECES);
+		for sq in 0..64 {
+			if let Some(tile) = b.tiles[sq / 8][sq % 8] {
+				let code = tile.piece as u8 + 1 + if tile.color == Color::White { 0 } else { 8 };
+				all.push((sq, code));
+			}
+		}
+		if all.len() != self.piece_count {
+			return None;
+		}
+
+		let mut squares = [0usize; TB_PIECES];
+		let mut pieces = [0u8; TB_PIECES];
+		let mut size = 0;
+		let mut lead_pawns = 0;
+		let mut file = 0;
+		let mut lead_code = 0;
+		if self.has_pawns {
+			// the leading pawns come first in the table, their color is the leading one
+			lead_code = self.get(0, 0).pieces[0] ^ flip_color;
+			for &(sq, code) in &all {
+				if code == lead_code {
+					squares[size] = sq ^ flip_squares;
+					size += 1;
+				}
+			}
+			lead_pawns = size;
+			let lead = (0..lead_pawns).max_by_key(|i| (ix.map_pawns[squares[*i]], usize::MAX - i))?;
+			squares.swap(0, lead);
+			file = file_of(squares[0]).min(7 - file_of(squares[0]));
+		}
+
+		if self.dtz {
+			let flags = self.get(stm, file).flags;
+			if (flags & FLAG_STM) as usize != stm && (!self.symmetric || self.has_pawns) {
+				return Some(TableValue::ChangeStm);
+			}
+		}
+
+		for &(sq, code) in &all {
+			if !self.has_pawns || code != lead_code {
+				squares[size] = sq ^ flip_squares;
+				pieces[size] = code ^ flip_color;
+				size += 1;
+			}
+		}
+		let d = self.get(stm, file);
+
+		// same order as in the table
+		for i in lead_pawns..size.saturating_sub(1) {
+			for j in i + 1..size {
+				if d.pieces[i] == pieces[j] {
+					pieces.swap(i, j);
+					squares.swap(i, j);
+					break;
+				}
+			}
+		}
+
+		// the leading piece goes to files a-d
+		if file_of(squares[0]) > 3 {
+			for sq in squares[..size].iter_mut() {
+				*sq ^= 7;
+			}
+		}
+
+		let mut idx: u64;
+		if self.has_pawns {
+			idx = ix.lead_pawn_idx[lead_pawns][squares[0]];
+			squares[1..lead_pawns].sort_by_key(|sq| ix.map_pawns[*sq]);
+			for (i, sq) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+				idx += ix.binomial[i][ix.map_pawns[*sq]];
+			}
+		} else {
+			// ranks 1-4, then under the a1-h8 diagonal
+			if rank_of(squares[0]) > 3 {
+				for sq in squares[..size].iter_mut() {
+					*sq ^= 56;
+				}
+			}
+			for i in 0..d.group_len[0] {
+				if off_a1h8(squares[i]) == 0 {
+					continue;
+				}
+				if off_a1h8(squares[i]) > 0 {
+					for sq in squares[i..size].iter_mut() {
+						*sq = ((*sq >> 3) | (*sq << 3)) & 63;
+					}
+				}
+				break;
+			}
+
+			if self.has_unique_pieces {
+				let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+				let adjust1 = (s1 > s0) as usize;
+				let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+				idx = if off_a1h8(s0) != 0 {
+					((ix.map_a1d1d4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2) as u64
+				} else if off_a1h8(s1) != 0 {
+					((6 * 63 + rank_of(s0) * 28 + ix.map_b1h1h7[s1]) * 62 + s2 - adjust2) as u64
+				} else if off_a1h8(s2) != 0 {
+					(6 * 63 * 62 + 4 * 28 * 62 + rank_of(s0) * 7 * 28 + (rank_of(s1) - adjust1) * 28 + ix.map_b1h1h7[s2]) as u64
+				} else {
+					(6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank_of(s0) * 7 * 6 + (rank_of(s1) - adjust1) * 6 + (rank_of(s2) - adjust2)) as u64
+				};
+			} else {
+				idx = ix.map_kk[ix.map_a1d1d4[squares[0]]][squares[1]] as u64;
+			}
+		}
+
+		// the other groups, each as a combination of the squares left
+		idx *= d.group_idx[0];
+		let mut start = d.group_len[0];
+		let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+		let mut next = 1;
+		while d.group_len[next] != 0 {
+			let len = d.group_len[next];
+			squares[start..start + len].sort_unstable();
+			let mut n = 0;
+			for i in 0..len {
+				let sq = squares[start + i];
+				let adjust = squares[..start].iter().filter(|s| sq > **s).count();
+				n += ix.binomial[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+			}
+			remaining_pawns = false;
+			idx += n * d.group_idx[next];
+			start += len;
+			next += 1;
+		}
+
+		let value = self.decompress_pairs(d, idx)?;
+		if self.dtz {
+			Some(TableValue::Value(self.map_dtz(file, value, wdl)?))
+		} else {
+			Some(TableValue::Value(value - 2))
+		}
+	}
+}
+
+/// Reads the Huffman code of a sub-table, returning the offset after it
+fn set_sizes(d: &mut PairsData, bytes: &[u8], mut data: usize) -> Result<usize, String> {
+	d.flags = u8_at(bytes, data)?;
+	data += 1;
+	if d.flags & FLAG_SINGLE_VALUE != 0 {
+		d.min_sym_len = u8_at(bytes, data)?;
+		return Ok(data + 1);
+	}
+
+	let groups = d.group_len.iter().position(|l| *l == 0).unwrap_or(TB_PIECES);
+	let tb_size = d.group_idx[groups];
+	d.block_size = 1 << u8_at(bytes, data)?;
+	d.span = 1 << u8_at(bytes, data + 1)?;
+	d.sparse_index_size = tb_size.div_ceil(d.span as u64) as usize;
+	let padding = u8_at(bytes, data + 2)? as usize;
+	d.num_blocks = u32_le(bytes, data + 3)? as usize;
+	d.block_length_size = d.num_blocks + padding;
+	d.max_sym_len = u8_at(bytes, data + 7)?;
+	d.min_sym_len = u8_at(bytes, data + 8)?;
+	data += 9;
+	if d.max_sym_len < d.min_sym_len || d.min_sym_len == 0 {
+		return Err(String::from("invalid symbol lengths"));
+	}
+	d.lowest_sym = data;
+
+	// canonical Huffman code: longer codes have lower values
+	let lengths = (d.max_sym_len - d.min_sym_len) as usize + 1;
+	d.base64 = vec![0; lengths];
+	for i in (0..lengths - 1).rev() {
+		let lowest = u16_le(bytes, data + 2 * i)? as u64;
+		let lowest_next = u16_le(bytes, data + 2 * (i + 1))? as u64;
+		d.base64[i] = (d.base64[i + 1] + lowest).wrapping_sub(lowest_next) / 2;
+	}
+	for (i, base) in d.base64.iter_mut().enumerate() {
+		*base = base.checked_shl((64 - i - d.min_sym_len as usize) as u32).unwrap_or(0);
+	}
+	data += 2 * lengths;
+
+	let symbols = u16_le(bytes, data)? as usize;
+	data += 2;
+	d.btree = data;
+	if data + 3 * symbols > bytes.len() {
+		return Err(String::from("truncated file"));
+	}
+	d.symlen = vec![0; symbols];
+	let mut visited = vec![false; symbols];
+	for sym in 0..symbols {
+		if !visited[sym] {
+			d.symlen[sym] = set_symlen(d, bytes, sym, &mut visited)?;
+		}
+	}
+	Ok(data + 3 * symbols + (symbols & 1))
+}
+
+/// Number of values (minus one) a symbol stands for, expanding its pairs
+fn set_symlen(d: &mut PairsData, bytes: &[u8], sym: usize, visited: &mut [bool]) -> Result<u8, String> {
+	visited[sym] = true;
+	let lr = &bytes[d.btree + 3 * sym..d.btree + 3 * sym + 3];
+	let right = ((lr[2] as usize) << 4) | (lr[1] as usize >> 4);
+	if right == 0xFFF {
+		return Ok(0);
+	}
+	let left = ((lr[1] as usize & 0xF) << 8) | lr[0] as usize;
+	if left >= d.symlen.len() || right >= d.symlen.len() {
+		return Err(String::from("invalid symbol"));
+	}
+	if !visited[left] {
+		d.symlen[left] = set_symlen(d, bytes, left, visited)?;
+	}
+	if !visited[right] {
+		d.symlen[right] = set_symlen(d, bytes, right, visited)?;
+	}
+	Ok(d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1))
+}
+
+/// Number of pieces on the board, kings included
+pub fn piece_count(b: &Board) -> usize {
+	b.tiles.iter().flatten().flatten().count()
+}
+
+/// Key of the material of a position, from the number of pieces of each kind of both sides:
+/// `side` is White's pieces in tables named after White's material, e.g. KQvK
+fn material_key(side: &[u8; 6], other: &[u8; 6]) -> u64 {
+	side.iter().chain(other.iter()).fold(0, |key, n| key << 4 | *n as u64)
+}
+
+/// Number of pieces of each kind of one side of a table name, e.g. "KRP"
+#[allow(dead_code)]
+fn name_counts(side: &str) -> Option<[u8; 6]> {
+	let mut counts = [0; 6];
+	for c in side.chars() {
+		counts["PNBRQK".find(c)?] += 1;
+	}
+	Some(counts)
+}
+
+/// The WDL and DTZ files of one material, read the first time they are probed
+struct TableFiles {
+	name: String,
+	symmetric: bool,
+	wdl: OnceLock<Option<Table>>,
+	dtz: OnceLock<Option<Table>>,
+}
+
+/// The set of tables found in the configured directories
+pub struct Tablebases {
+	paths: Vec<PathBuf>,
+	/// Tables found, e.g. `KQvK`, with a WDL and possibly a DTZ file, by material key
+	tables: HashMap<u64, TableFiles>,
+	/// Largest number of pieces of the tables found
+	max_pieces: usize,
+}
+
+impl fmt::Debug for Tablebases {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Tablebases({:?}, {} tables, up to {} pieces)", self.paths, self.tables.len(), self.max_pieces)
+	}
+}
+
+impl PartialEq for Tablebases {
+	fn eq(&self, other: &Self) -> bool {
+		self.paths == other.paths && self.tables.len() == other.tables.len()
+			&& self.tables.keys().all(|k| other.tables.contains_key(k))
+	}
+}
+impl Eq for Tablebases {}
+
+impl Tablebases {
+	/// Looks for tables in the given directories, separated by `:` (`;` on Windows)
+	#[allow(dead_code)]
+	pub fn open(path: &str) -> Result<Tablebases, String> {
+		let separator = if cfg!(windows) { ';' } else { ':' };
+		let paths: Vec<PathBuf> = path.split(separator).filter(|p| !p.is_empty()).map(PathBuf::from).collect();
+		let mut names = HashSet::new();
+		for dir in &paths {
+			let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+			for entry in entries.flatten() {
+				let file = entry.file_name().to_string_lossy().to_string();
+				if let Some(name) = file.strip_suffix(WDL_SUFFIX) {
+					names.insert(name.to_string());
+				}
+			}
+		}
+		let tb = Tablebases::with_tables(paths, names);
+		if tb.tables.is_empty() {
+			return Err(format!("{}: no Syzygy tables found", path));
+		}
+		Ok(tb)
+	}
+
+	#[allow(dead_code)]
+	fn with_tables(paths: Vec<PathBuf>, names: HashSet<String>) -> Tablebases {
+		let mut tables = HashMap::new();
+		for name in names {
+			let counts = name.split_once('v').and_then(|(w, b)| Some((name_counts(w)?, name_counts(b)?)));
+			if let Some((white, black)) = counts {
+				let files = TableFiles { name, symmetric: white == black, wdl: OnceLock::new(), dtz: OnceLock::new() };
+				tables.insert(material_key(&white, &black), files);
+			}
+		}
+		let max_pieces = tables.values().map(|t| t.name.len() - 1).max().unwrap_or(0).min(TB_PIECES);
+		Tablebases { paths, tables, max_pieces }
+	}
+
+	fn table<'a>(&self, files: &'a TableFiles, dtz: bool) -> Option<&'a Table> {
+		let cell = if dtz { &files.dtz } else { &files.wdl };
+		cell.get_or_init(|| {
+			let file = format!("{}{}", files.name, if dtz { DTZ_SUFFIX } else { WDL_SUFFIX });
+			let (white, black) = files.name.split_once('v')?;
+			let bytes = self.paths.iter().find_map(|dir| fs::read(dir.join(&file)).ok())?;
+			Table::parse(bytes, dtz, white, black).map_err(|e| eprintln!("{}: {}", file, e)).ok()
+		}).as_ref()
+	}
+
+	/// Number of pieces, kings included, of the largest tables
+	pub fn max_pieces(&self) -> usize {
+		self.max_pieces
+	}
+
+	/// Whether the position may be in the tables
+	fn probeable(&self, b: &Board) -> bool {
+		piece_count(b) <= self.max_pieces
+			&& !b.can_castle_ks.iter().chain(b.can_castle_qs.iter()).any(|c| *c)
+	}
+
+	/// Probes the table of the position's material
+	fn probe_table(&self, b: &Board, dtz: bool, wdl: Wdl) -> Option<TableValue> {
+		let mut counts = [[0u8; 6]; 2];
+		for tile in b.tiles.iter().flatten().flatten() {
+			counts[tile.color as usize][tile.piece as usize] += 1;
+		}
+		let (white, black) = (&counts[1], &counts[0]);
+		if white.iter().chain(black.iter()).sum::<u8>() == 2 {
+			return Some(TableValue::Value(0));
+		}
+		let (files, flipped) = match self.tables.get(&material_key(white, black)) {
+			Some(files) => (files, files.symmetric && b.player == Color::Black),
+			None => (self.tables.get(&material_key(black, white))?, true),
+		};
+		self.table(files, dtz)?.probe(b, flipped, wdl)
+	}
+
+	/// WDL of the position, also looking at captures (and pawn moves with `zeroing`), since tables
+	/// may store any value for positions where such a move is the best. The flag tells whether
+	/// the best move is a zeroing one, whose DTZ is not stored in the tables
+	fn search(&self, b: &Board, zeroing: bool) -> Option<(Wdl, bool)> {
+		let moves = b.generate_all();
+		let mut searched = 0;
+		let mut best = Wdl::Loss;
+		for (mv, child) in &moves {
+			let capture = b.at(mv.t_pos).is_some();
+			let pawn = b.at(mv.f_pos).is_some_and(|t| t.piece == Piece::Pawn);
+			if !(capture || zeroing && pawn) {
+				continue;
+			}
+			searched += 1;
+			let value = -self.search(child, false)?.0;
+			if value > best {
+				best = value;
+				if value == Wdl::Win {
+					return Some((value, true));
+				}
+			}
+		}
+
+		// with all the moves searched, the table value is not needed
+		let all_searched = searched > 0 && searched == moves.len();
+		let value = if all_searched {
+			best
+		} else {
+			match self.probe_table(b, false, Wdl::Draw)? {
+				TableValue::Value(v) => Wdl::from_i32(v),
+				TableValue::ChangeStm => return None,
+			}
+		};
+		if best >= value {
+			return Some((best, best > Wdl::Draw || all_searched));
+		}
+		Some((value, false))
+	}
+
+	/// Win, draw or loss for the side to move, if the position is in the tables
+	pub fn probe_wdl(&self, b: &Board) -> Option<Wdl> {
+		if !self.probeable(b) {
+			return None;
+		}
+		self.search(b, false).map(|(wdl, _)| wdl)
+	}
+
+	/// Plies to the next zeroing move: positive when winning, negative when losing,
+	/// 0 for draws. Beyond 100 for cursed wins and blessed losses
+	fn probe_dtz(&self, b: &Board) -> Option<i32> {
+		let (wdl, zeroing_best) = self.search(b, true)?;
+		if wdl == Wdl::Draw {
+			return Some(0);
+		}
+		if zeroing_best {
+			return Some(dtz_before_zeroing(wdl));
+		}
+		match self.probe_table(b, true, wdl)? {
+			TableValue::Value(dtz) => {
+				let cursed = wdl == Wdl::CursedWin || wdl == Wdl::BlessedLoss;
+				Some((dtz + if cursed { 100 } else { 0 }) * wdl.sign())
+			},
+			TableValue::ChangeStm => {
+				// the table has the other side to move: one ply search
+				let mut min_dtz = i32::MAX;
+				for (mv, child) in b.generate_all() {
+					let zeroing = b.at(mv.t_pos).is_some() || b.at(mv.f_pos).is_some_and(|t| t.piece == Piece::Pawn);
+					let mut dtz = if zeroing {
+						-dtz_before_zeroing(self.search(&child, false)?.0)
+					} else {
+						-self.probe_dtz(&child)?
+					};
+					if dtz == 1 && child.is_king_in_check(child.player) && child.generate_all().is_empty() {
+						min_dtz = 1;
+					}
+					if !zeroing {
+						dtz += dtz.signum();
+					}
+					if dtz < min_dtz && dtz.signum() == wdl.sign() {
+						min_dtz = dtz;
+					}
+				}
+				Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+			},
+		}
+	}
+
+	/// The best move according to the tables, with the resulting WDL for the side to move:
+	/// the fastest win within the 50-move rule, or the longest resistance
+	pub fn root_move(&self, b: &Board) -> Option<(Move, Wdl)> {
+		if !self.probeable(b) {
+			return None;
+		}
+		let mut best: Option<((i32, i32), Move)> = None;
+		for (mv, child) in b.generate_all() {
+			let zeroing = b.at(mv.t_pos).is_some() || b.at(mv.f_pos).is_some_and(|t| t.piece == Piece::Pawn);
+			let mut dtz = if zeroing {
+				dtz_before_zeroing(-self.search(&child, false)?.0)
+			} else {
+				let dtz = -self.probe_dtz(&child)?;
+				dtz + dtz.signum()
+			};
+			if dtz == 2 && child.is_king_in_check(child.player) && child.generate_all().is_empty() {
+				dtz = 1;
+			}
+			// as in the reference code, wins rank first unless they only zero on the 100th ply,
+			// losses zeroing within 50 moves rank last
+			let rank = if dtz > 0 {
+				if dtz <= 99 { MAX_DTZ } else { MAX_DTZ - dtz }
+			} else if dtz < 0 {
+				if -dtz * 2 < 100 { -MAX_DTZ } else { -MAX_DTZ - dtz }
+			} else {
+				0
+			};
+			// then the shortest win, or the longest loss
+			let key = (rank, -dtz);
+			if best.is_none_or(|(k, _)| key > k) {
+				best = Some((key, mv));
+			}
+		}
+		let ((_, shortest), mv) = best?;
+		let wdl = match -shortest {
+			d if d > 100 => Wdl::CursedWin,
+			d if d > 0 => Wdl::Win,
+			0 => Wdl::Draw,
+			d if d >= -100 => Wdl::Loss,
+			_ => Wdl::BlessedLoss,
+		};
+		Some((mv, wdl))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+	use std::env;
+	use crate::board::Board;
+	use crate::syzygy::{Tablebases,Wdl,indexing};
+
+	/// KQvK and KRvK as written by testdata/syzygy/generate.py, which shares this module's
+	/// reading of the format. The canonical tables are used instead from `SYZYGY_PATH`, if set
+	fn fixture() -> Tablebases {
+		match env::var("SYZYGY_PATH") {
+			Ok(path) => Tablebases::open(&path).unwrap(),
+			Err(_) => Tablebases::open(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/syzygy")).unwrap(),
+		}
+	}
+
+	/// The same position with the colors swapped, which is looked up in the table flipped
+	fn flip(fen: &str) -> String {
+		let fields: Vec<&str> = fen.split(' ').collect();
+		let ranks: Vec<String> = fields[0].split('/').rev().map(|rank| rank.chars().map(|c| {
+			if c.is_ascii_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }
+		}).collect()).collect();
+		format!("{} {} - - 0 1", ranks.join("/"), if fields[1] == "w" { "b" } else { "w" })
+	}
+
+	#[test]
+	pub fn test_indexing() {
+		let ix = indexing();
+		// 462 placements of the kings, 10 squares in the a1-d1-d4 triangle
+		let kk: HashSet<usize> = ix.map_kk.iter().flatten().copied().collect();
+		debug_assert_eq!(kk.iter().max(), Some(&461));
+		debug_assert_eq!(ix.map_a1d1d4.iter().max(), Some(&9));
+		debug_assert_eq!(ix.map_b1h1h7.iter().max(), Some(&27));
+		let pawns: HashSet<usize> = (8..56).map(|sq| ix.map_pawns[sq]).collect();
+		debug_assert_eq!(pawns.len(), 48);
+		debug_assert_eq!(ix.binomial[2][5], 10);
+		debug_assert_eq!(ix.lead_pawns_size[1][0], 6);
+	}
+
+	#[test]
+	pub fn test_wdl() {
+		debug_assert_eq!(-Wdl::Win, Wdl::Loss);
+		debug_assert_eq!(-Wdl::BlessedLoss, Wdl::CursedWin);
+		debug_assert!(Wdl::Win.value(3) > Wdl::CursedWin.value(0));
+		let tb = Tablebases::with_tables(Vec::new(), ["KQvK".to_string()].iter().cloned().collect());
+		// bare kings need no table, missing tables give no result
+		debug_assert_eq!(tb.probe_wdl(&Board::from_fen("8/8/4k3/8/8/3K4/8/8 w - - 0 1").unwrap()), Some(Wdl::Draw));
+		debug_assert_eq!(tb.probe_wdl(&Board::from_fen("8/8/4k3/8/8/3K4/8/7Q w - - 0 1").unwrap()), None);
+		debug_assert_eq!(tb.probe_wdl(&Board::from_fen("8/8/4k3/8/8/R2K4/8/7Q w - - 0 1").unwrap()), None);
+		debug_assert!(Tablebases::open("/nonexistent").is_err());
+	}
+
+	#[test]
+	pub fn test_probe() {
+		let tb = fixture();
+		let cases = [
+			// mates in one
+			("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1", Wdl::Win, 1),
+			("k7/8/1K6/8/8/8/8/7R w - - 0 1", Wdl::Win, 1),
+			// mated, and in check with a single move before the mate
+			("3k4/3Q4/2K5/8/8/8/8/8 b - - 0 1", Wdl::Loss, -1),
+			("k7/8/1K6/8/8/8/8/7Q b - - 0 1", Wdl::Loss, -2),
+			// stalemate, and a capture of the unprotected queen
+			("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1", Wdl::Draw, 0),
+			("8/8/8/8/8/8/1k6/Q6K b - - 0 1", Wdl::Draw, 0),
+			("8/8/8/8/8/8/1k6/R6K b - - 0 1", Wdl::Draw, 0),
+			// the longest mate of KRvK takes 16 moves
+			("8/8/8/8/8/3k4/2R5/1K6 w - - 0 1", Wdl::Win, 31),
+		];
+		for (fen, wdl, dtz) in cases.iter() {
+			for fen in [fen.to_string(), flip(fen)] {
+				let b = Board::from_fen(&fen).unwrap();
+				debug_assert_eq!(tb.probe_wdl(&b), Some(*wdl), "{}", fen);
+				debug_assert_eq!(tb.probe_dtz(&b), Some(*dtz), "{}", fen);
+			}
+		}
+	}
+
+	#[test]
+	pub fn test_root_move() {
+		let tb = fixture();
+		for fen in ["8/8/8/4k3/8/8/8/KQ6 w - - 0 1", "8/8/3k4/8/8/8/8/R3K3 b - - 0 1"] {
+			for fen in [fen.to_string(), flip(fen)] {
+				// both sides playing the tables' moves, the mate comes after exactly DTZ plies
+				let mut b = Board::from_fen(&fen).unwrap();
+				let dtz = tb.probe_dtz(&b).unwrap();
+				let winner = if dtz > 0 { b.player } else { b.player.swap() };
+				let mut plies = 0;
+				while let Some((mv, wdl)) = tb.root_move(&b) {
+					debug_assert_eq!(wdl, if b.player == winner { Wdl::Win } else { Wdl::Loss }, "{}", fen);
+					b = b.generate_all().into_iter().find(|(m, _)| *m == mv).unwrap().1;
+					plies += 1;
+				}
+				debug_assert!(b.generate_all().is_empty() && b.is_king_in_check(b.player), "{}", fen);
+				debug_assert_eq!(plies, dtz.abs(), "{}", fen);
+				debug_assert_ne!(b.player, winner);
+			}
+		}
+	}
+
+	/// Known values of the canonical 3-piece tables, from `SYZYGY_PATH` (e.g. a copy of
+	/// https://tablebase.lichess.ovh/tables/standard/3-4-5/ with at least KQvK, KRvK and KPvK).
+	/// Skipped when it isn't set
+	#[test]
+	pub fn test_reference_tables() {
+		let path = match env::var("SYZYGY_PATH") {
+			Ok(path) => path,
+			Err(_) => return eprintln!("SYZYGY_PATH not set, skipping the canonical tables"),
+		};
+		let tb = Tablebases::open(&path).unwrap();
+		let cases = [
+			// mate in one, stalemate, and the longest mate of KRvK, in 16 moves
+			("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1", Some(Wdl::Win), Some(1)),
+			("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1", Some(Wdl::Draw), Some(0)),
+			("8/8/8/8/8/3k4/2R5/1K6 w - - 0 1", Some(Wdl::Win), Some(31)),
+			("k7/8/1K6/8/8/8/8/7Q b - - 0 1", Some(Wdl::Loss), Some(-2)),
+			("8/8/8/8/8/8/1k6/R6K b - - 0 1", Some(Wdl::Draw), Some(0)),
+			// KPvK: the opposition decides
+			("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1", Some(Wdl::Draw), Some(0)),
+			("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1", Some(Wdl::Loss), None),
+			// the king in front of its pawn on the sixth rank wins either way
+			("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1", Some(Wdl::Win), None),
+			("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1", Some(Wdl::Loss), None),
+			// promotes at once
+			("8/4P3/8/8/8/8/k7/4K3 w - - 0 1", Some(Wdl::Win), Some(1)),
+			// a rook pawn with the defending king in front of it
+			("8/8/8/8/8/k7/P7/K7 w - - 0 1", Some(Wdl::Draw), Some(0)),
+		];
+		for (fen, wdl, dtz) in cases.iter() {
+			for fen in [fen.to_string(), flip(fen)] {
+				let b = Board::from_fen(&fen).unwrap();
+				debug_assert_eq!(tb.probe_wdl(&b), *wdl, "{}", fen);
+				if let Some(dtz) = dtz {
+					debug_assert_eq!(tb.probe_dtz(&b), Some(*dtz), "{}", fen);
+				}
+			}
+		}
+	}
+}
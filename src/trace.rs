@@ -0,0 +1,159 @@
+use std::fmt;
+
+use crate::board::{Board, Color};
+use crate::evaluation::{Value, MAX_PHASE, taper};
+use crate::misc::Evaluation;
+use crate::params::{EvalParams, Weight};
+use crate::pawns::{pawn_info, pawn_structure};
+use crate::positional::positional;
+
+/// Breakdown of an evaluation: each term as (middlegame, endgame) per color.
+/// The simplified evaluation only has material and piece-square terms, the network none
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvalTrace {
+	/// The evaluation broken down
+	pub evaluation: Evaluation,
+	pub material: [Weight; 2],
+	pub pst: [Weight; 2],
+	pub pawns: [Weight; 2],
+	pub mobility: [Weight; 2],
+	pub king_safety: [Weight; 2],
+	/// Bishop pair, rooks on (semi-)open files and on the seventh rank, knight outposts
+	pub pieces: [Weight; 2],
+	/// Game phase, from `MAX_PHASE` (opening) to 0 (endgame)
+	pub phase: i32,
+	/// Score of a known endgame, which replaces the terms
+	pub endgame: Option<Value>,
+	/// Score from White's point of view, as given by the evaluation
+	pub total: Value,
+}
+
+impl EvalTrace {
+	/// All terms, with their names
+	pub fn terms(&self) -> [(&'static str, [Weight; 2]); 6] {
+		[
+			("material", self.material),
+			("pst", self.pst),
+			("pawns", self.pawns),
+			("mobility", self.mobility),
+			("king_safety", self.king_safety),
+			("pieces", self.pieces),
+		]
+	}
+
+	/// Sum of the terms, tapered by the game phase
+	pub fn tapered(&self) -> Value {
+		let (mut mg, mut eg) = (0, 0);
+		for (_, t) in self.terms() {
+			mg += t[Color::White as usize].0 - t[Color::Black as usize].0;
+			eg += t[Color::White as usize].1 - t[Color::Black as usize].1;
+		}
+		taper(mg, eg, self.phase)
+	}
+
+	/// Sets the total as the evaluations do: the score of a known endgame, if any,
+	/// or the tapered sum of the terms
+	pub fn set_total(&mut self, b: &Board) {
+		self.endgame = b.endgame_value();
+		self.total = self.endgame.unwrap_or_else(|| self.tapered());
+	}
+
+	/// The trace as a JSON object: each term has `white` and `black` [mg, eg] pairs
+	#[allow(dead_code)]
+	pub fn to_json(&self) -> String {
+		let pair = |w: Weight| format!("[{},{}]", w.0, w.1);
+		let terms: Vec<String> = self.terms().iter().map(|(name, t)| format!("\"{}\":{{\"white\":{},\"black\":{}}}",
+			name, pair(t[Color::White as usize]), pair(t[Color::Black as usize]))).collect();
+		let endgame = self.endgame.map_or(String::from("null"), |v| v.to_string());
+		format!("{{\"eval\":\"{}\",{},\"phase\":{},\"max_phase\":{},\"endgame\":{},\"total\":{}}}",
+			self.name(), terms.join(","), self.phase, MAX_PHASE, endgame, self.total)
+	}
+
+	/// Name of the evaluation, as in the `--eval` option
+	fn name(&self) -> String {
+		format!("{:?}", self.evaluation).to_lowercase()
+	}
+}
+
+impl fmt::Display for EvalTrace {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let (white, black) = (Color::White as usize, Color::Black as usize);
+		writeln!(f, "Evaluation: {}", self.name())?;
+		if self.evaluation == Evaluation::Nnue {
+			writeln!(f, "(no breakdown of the network output)")?;
+			return write!(f, "Total: {} cp (White's point of view)", self.total);
+		}
+		writeln!(f, "        Term |    White    |    Black    |    Total")?;
+		writeln!(f, "             |   MG    EG  |   MG    EG  |   MG    EG")?;
+		writeln!(f, "-------------+-------------+-------------+-------------")?;
+		for (name, t) in self.terms() {
+			let (w, b) = (t[white], t[black]);
+			writeln!(f, "{:>12} | {:>5} {:>5} | {:>5} {:>5} | {:>5} {:>5}",
+				name, w.0, w.1, b.0, b.1, w.0 - b.0, w.1 - b.1)?;
+		}
+		writeln!(f, "-------------+-------------+-------------+-------------")?;
+		writeln!(f, "Phase: {}/{} (middlegame weight)", self.phase, MAX_PHASE)?;
+		if let Some(v) = self.endgame {
+			writeln!(f, "Known endgame: {} cp, instead of the terms", v)?;
+		}
+		write!(f, "Total: {} cp (White's point of view)", self.total)
+	}
+}
+
+impl Board {
+	/// Computes the classic evaluation term by term
+	pub fn trace(&self, params: &EvalParams) -> EvalTrace {
+		let (material, pst) = self.material_pst(params);
+		let pawn_info = pawn_info(self);
+		let pawns = pawn_structure(self, &pawn_info, params);
+		let pos = positional(self, &pawn_info, params);
+		let mut trace = EvalTrace {
+			evaluation: Evaluation::Classic,
+			material,
+			pst,
+			pawns,
+			mobility: pos.mobility,
+			king_safety: pos.king_safety,
+			pieces: pos.pieces,
+			phase: self.game_phase(),
+			endgame: None,
+			total: 0,
+		};
+		trace.set_total(self);
+		trace
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::board::{Board,Color};
+	use crate::evaluation::{Valuable,new_evaluator};
+	use crate::misc::{Evaluation,OPTS_DEFAULT};
+	use crate::params::PARAMS_DEFAULT;
+
+	#[test]
+	pub fn test_trace() {
+		let b = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0").unwrap();
+		let trace = b.trace(&PARAMS_DEFAULT);
+		debug_assert_eq!(trace.total, 0);
+		debug_assert_eq!(trace.material[Color::White as usize], trace.material[Color::Black as usize]);
+		let b = Board::from_fen("4k3/8/8/3p4/8/8/4P3/3QK3 w - - 0 0").unwrap();
+		debug_assert_eq!(b.trace(&PARAMS_DEFAULT).total, b.value());
+		debug_assert!(b.trace(&PARAMS_DEFAULT).to_json().starts_with("{\"eval\":\"classic\",\"material\":{\"white\":["));
+		// the evaluation selected in the options
+		let mut opts = OPTS_DEFAULT.clone();
+		opts.eval = Evaluation::Simplified;
+		let eval = new_evaluator(&opts);
+		let trace = eval.trace(&b);
+		debug_assert_eq!((trace.evaluation, trace.total), (Evaluation::Simplified, eval.evaluate(&b)));
+		debug_assert_eq!(trace.mobility, [(0, 0); 2]);
+		debug_assert!(trace.to_string().starts_with("Evaluation: simplified"));
+		// known endgames replace the terms, in the tuner as in the search
+		let b = Board::from_fen("k7/8/2K5/8/8/8/7Q/8 w - - 0 1").unwrap();
+		let trace = b.trace(&PARAMS_DEFAULT);
+		debug_assert_eq!((trace.endgame, trace.total), (b.endgame_value(), b.value()));
+		debug_assert_ne!(trace.total, trace.tapered());
+		debug_assert!(trace.to_json().ends_with(&format!("\"endgame\":{},\"total\":{}}}", trace.total, trace.total)));
+		debug_assert_eq!(eval.trace(&b).total, eval.evaluate(&b));
+	}
+}
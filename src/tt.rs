@@ -0,0 +1,159 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::board::{Move, Pos};
+use crate::evaluation::{Value, MAX_PLY};
+use crate::syzygy::TB_WIN;
+
+/* 
+ * Lock-free transposition table shared by all search threads.
+ * Each slot stores the position key XOR-ed with the entry data, so that
+ * an entry torn by concurrent writes is detected and ignored:
+ * https://www.chessprogramming.org/Shared_Hash_Table#Lockless
+*/
+
+pub const DEFAULT_TT_SIZE_MB: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Bound {
+	/// The score is exact
+	Exact = 0,
+	/// The score is a lower bound (the search failed high)
+	Lower = 1,
+	/// The score is an upper bound (the search failed low)
+	Upper = 2,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TTEntry {
+	pub score: Value,
+	pub depth: u8,
+	pub bound: Bound,
+	pub mv: Option<Move>,
+}
+
+impl TTEntry {
+	fn pack(&self) -> u64 {
+		let mut data = (self.score as u16) as u64;
+		data |= (self.depth as u64) << 16;
+		data |= (self.bound as u64) << 24;
+		if let Some(mv) = self.mv {
+			data |= 1 << 26;
+			data |= ((mv.f_pos.row * 8 + mv.f_pos.col) as u64) << 27;
+			data |= ((mv.t_pos.row * 8 + mv.t_pos.col) as u64) << 33;
+		}
+		data
+	}
+
+	fn unpack(data: u64) -> TTEntry {
+		let sq = |s: u64| Pos::at((s % 8) as i8, (s / 8) as i8).unwrap();
+		TTEntry {
+			score: (data & 0xFFFF) as u16 as Value,
+			depth: ((data >> 16) & 0xFF) as u8,
+			bound: match (data >> 24) & 0x3 {
+				0 => Bound::Exact,
+				1 => Bound::Lower,
+				_ => Bound::Upper,
+			},
+			mv: if data & (1 << 26) != 0 {
+				Some(Move { f_pos: sq((data >> 27) & 0x3F), t_pos: sq((data >> 33) & 0x3F) })
+			} else {
+				None
+			},
+		}
+	}
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+	key: AtomicU64,
+	data: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct TranspositionTable {
+	slots: Vec<Slot>,
+	mask: usize,
+}
+
+impl TranspositionTable {
+	pub fn new(size_mb: usize) -> Self {
+		let wanted = (size_mb * 1024 * 1024 / std::mem::size_of::<Slot>()).max(1);
+		// largest power of two that fits
+		let len = 1usize << (usize::BITS - 1 - wanted.leading_zeros());
+		let mut slots = Vec::with_capacity(len);
+		slots.resize_with(len, Slot::default);
+		TranspositionTable { slots, mask: len - 1 }
+	}
+
+	#[allow(dead_code)]
+	pub fn clear(&self) {
+		for slot in self.slots.iter() {
+			slot.key.store(0, Ordering::Relaxed);
+			slot.data.store(0, Ordering::Relaxed);
+		}
+	}
+
+	pub fn probe(&self, hash: u64) -> Option<TTEntry> {
+		let slot = &self.slots[hash as usize & self.mask];
+		let data = slot.data.load(Ordering::Relaxed);
+		if slot.key.load(Ordering::Relaxed) ^ data == hash && data != 0 {
+			Some(TTEntry::unpack(data))
+		} else {
+			None
+		}
+	}
+
+	/// Depth-preferred replacement: an entry for the same position
+	/// is only overwritten by a search that went at least as deep
+	pub fn store(&self, hash: u64, entry: TTEntry) {
+		let slot = &self.slots[hash as usize & self.mask];
+		let old_data = slot.data.load(Ordering::Relaxed);
+		if slot.key.load(Ordering::Relaxed) ^ old_data == hash && TTEntry::unpack(old_data).depth > entry.depth {
+			return;
+		}
+		let data = entry.pack();
+		slot.key.store(hash ^ data, Ordering::Relaxed);
+		slot.data.store(data, Ordering::Relaxed);
+	}
+}
+
+/// Mate and tablebase scores are relative to the root. In the table they are stored
+/// relative to the node, so they stay valid when reached through another path
+#[inline]
+pub fn score_to_tt(v: Value, ply: u8) -> Value {
+	if v >= TB_WIN - MAX_PLY {
+		v + ply as Value
+	} else if v <= -TB_WIN + MAX_PLY {
+		v - ply as Value
+	} else {
+		v
+	}
+}
+
+#[inline]
+pub fn score_from_tt(v: Value, ply: u8) -> Value {
+	if v >= TB_WIN - MAX_PLY {
+		v - ply as Value
+	} else if v <= -TB_WIN + MAX_PLY {
+		v + ply as Value
+	} else {
+		v
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::tt::{TranspositionTable,TTEntry,Bound};
+
+	#[test]
+	pub fn test_tt_store_probe() {
+		let tt = TranspositionTable::new(1);
+		let entry = TTEntry { score: -1234, depth: 5, bound: Bound::Lower, mv: Some("e7e8".parse().unwrap()) };
+		tt.store(0xDEAD_BEEF, entry);
+		debug_assert_eq!(tt.probe(0xDEAD_BEEF), Some(entry));
+		debug_assert_eq!(tt.probe(0xBEEF_DEAD), None);
+		// shallower searches don't replace deeper ones
+		tt.store(0xDEAD_BEEF, TTEntry { depth: 2, ..entry });
+		debug_assert_eq!(tt.probe(0xDEAD_BEEF), Some(entry));
+	}
+}
@@ -0,0 +1,237 @@
+use std::fs;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::board::Board;
+use crate::params::EvalParams;
+
+/*
+ * Texel's tuning method: https://www.chessprogramming.org/Texel%27s_Tuning_Method
+ * The evaluation of quiet positions is mapped to an expected game result by a sigmoid,
+ * and the parameters are adjusted one unit at a time (local search) as long as
+ * the mean squared error against the actual game results decreases.
+*/
+
+/// A quiet position with the result of the game it was taken from
+pub struct TunePosition {
+	board: Board,
+	/// 1.0 if White won, 0.5 for a draw, 0.0 if Black won
+	result: f64,
+}
+
+/// Result markers, in the order they are looked for
+const RESULTS: [(&str, f64); 6] = [
+	("1/2-1/2", 0.5), ("1-0", 1.0), ("0-1", 0.0),
+	("[0.5]", 0.5), ("[1.0]", 1.0), ("[0.0]", 0.0),
+];
+
+impl TunePosition {
+	/// Parses a line with a FEN followed by the game result,
+	/// either as `1-0`, `0-1`, `1/2-1/2` or as `[1.0]`, `[0.0]`, `[0.5]`
+	pub fn parse(line: &str) -> Option<TunePosition> {
+		let result = RESULTS.iter().find(|(marker, _)| line.contains(marker))?.1;
+		// the position fields, without the move counters or opcodes that may follow
+		let fen: Vec<&str> = line.split_whitespace().take(4).collect();
+		let board = Board::from_fen(&fen.join(" ")).ok()?;
+		Some(TunePosition { board, result })
+	}
+}
+
+/// Reads a file of positions, one per line. Empty lines and lines starting with `#` are skipped
+pub fn load_positions(path: &str) -> Result<Vec<TunePosition>, String> {
+	let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+	let mut positions = Vec::new();
+	for (n, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		match TunePosition::parse(line) {
+			Some(p) => positions.push(p),
+			None => return Err(format!("{}:{}: expected a FEN and a game result", path, n + 1)),
+		}
+	}
+	Ok(positions)
+}
+
+/// Expected result for White of a position evaluated `score` centipawns
+#[inline]
+fn sigmoid(score: f64, k: f64) -> f64 {
+	1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+/// A thread that evaluates its share of the positions for every error computation.
+/// It lives as long as the tuner, so that its pawn hash table (one per thread) stays warm
+struct Worker {
+	/// Parameters and sigmoid scaling to evaluate with
+	jobs: Sender<(Arc<EvalParams>, f64)>,
+	/// Sum of the squared errors of each job
+	results: Receiver<f64>,
+	handle: JoinHandle<()>,
+}
+
+impl Worker {
+	fn spawn(positions: Vec<TunePosition>) -> Self {
+		let (jobs, job_rx) = mpsc::channel::<(Arc<EvalParams>, f64)>();
+		let (result_tx, results) = mpsc::channel();
+		let handle = thread::spawn(move || {
+			for (params, k) in job_rx {
+				let sum = positions.iter().map(|p| {
+					// the trace is not cached in the board, unlike `value()`
+					let score = p.board.trace(&params).total as f64;
+					(p.result - sigmoid(score, k)).powi(2)
+				}).sum::<f64>();
+				if result_tx.send(sum).is_err() {
+					break;
+				}
+			}
+		});
+		Worker { jobs, results, handle }
+	}
+}
+
+pub struct Tuner {
+	/// One per thread, each with its share of the positions
+	workers: Vec<Worker>,
+	count: usize,
+	/// Scaling constant of the sigmoid
+	pub k: f64,
+}
+
+impl Tuner {
+	pub fn new(positions: Vec<TunePosition>, threads: usize) -> Self {
+		let count = positions.len();
+		let threads = threads.max(1);
+		let chunk_size = count.div_ceil(threads).max(1);
+		let mut chunks: Vec<Vec<TunePosition>> = Vec::new();
+		for p in positions {
+			match chunks.last_mut() {
+				Some(chunk) if chunk.len() < chunk_size => chunk.push(p),
+				_ => chunks.push(vec![p]),
+			}
+		}
+		let workers = chunks.into_iter().map(Worker::spawn).collect();
+		Tuner { workers, count, k: 1.0 }
+	}
+
+	/// Mean squared error of the evaluation with the given parameters
+	pub fn error(&self, params: &EvalParams) -> f64 {
+		let job = Arc::new(params.clone());
+		for w in &self.workers {
+			// a worker that died doesn't answer, and counts as no error below
+			let _ = w.jobs.send((job.clone(), self.k));
+		}
+		let sum: f64 = self.workers.iter().map(|w| w.results.recv().unwrap_or(0.0)).sum();
+		sum / self.count.max(1) as f64
+	}
+
+	/// Picks the sigmoid scaling that best fits the current evaluation
+	pub fn fit_k(&mut self, params: &EvalParams) -> f64 {
+		let (mut best_k, mut best_err) = (self.k, self.error(params));
+		for step in [0.1, 0.01, 0.001] {
+			let center = best_k;
+			for i in -10..=10 {
+				let k = center + i as f64 * step;
+				if k <= 0.0 {
+					continue;
+				}
+				self.k = k;
+				let err = self.error(params);
+				if err < best_err {
+					best_k = k;
+					best_err = err;
+				}
+			}
+		}
+		self.k = best_k;
+		best_k
+	}
+
+	/// Local search over every parameter, for at most `iterations` passes.
+	/// `on_iteration` is called after each pass with its number, the error and the parameters so far
+	pub fn tune<F>(&mut self, params: &EvalParams, iterations: usize, mut on_iteration: F) -> EvalParams
+			where F: FnMut(usize, f64, &EvalParams) {
+		let mut params = params.clone();
+		let mut best_err = self.error(&params);
+		let lengths: Vec<(&'static str, usize)> = params.weights_mut().iter().map(|(n, w)| (*n, w.len())).collect();
+		for it in 0..iterations {
+			let mut improved = false;
+			for (field, (name, len)) in lengths.iter().enumerate() {
+				for idx in 0..*len {
+					// the king is never traded, its value makes no difference
+					if *name == "piece_value" && idx == 5 {
+						continue;
+					}
+					for component in 0..2 {
+						for delta in [1, -1] {
+							adjust(&mut params, field, idx, component, delta);
+							let err = self.error(&params);
+							if err < best_err {
+								best_err = err;
+								improved = true;
+								break;
+							}
+							adjust(&mut params, field, idx, component, -delta);
+						}
+					}
+				}
+			}
+			on_iteration(it + 1, best_err, &params);
+			if !improved {
+				break;
+			}
+		}
+		params
+	}
+}
+
+impl Drop for Tuner {
+	fn drop(&mut self) {
+		for w in self.workers.drain(..) {
+			// without jobs to wait for, the thread ends
+			drop(w.jobs);
+			let _ = w.handle.join();
+		}
+	}
+}
+
+/// Adds `delta` to the middlegame (`component` 0) or endgame (1) value of a weight
+fn adjust(params: &mut EvalParams, field: usize, idx: usize, component: usize, delta: i32) {
+	let mut fields = params.weights_mut();
+	let w = &mut fields[field].1[idx];
+	if component == 0 {
+		w.0 += delta;
+	} else {
+		w.1 += delta;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::params::PARAMS_DEFAULT;
+	use crate::tune::{TunePosition,Tuner};
+
+	#[test]
+	pub fn test_parse_position() {
+		let p = TunePosition::parse("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 [1.0]").unwrap();
+		debug_assert_eq!(p.result, 1.0);
+		let p = TunePosition::parse("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1; c9 \"1/2-1/2\";").unwrap();
+		debug_assert_eq!(p.result, 0.5);
+		debug_assert!(TunePosition::parse("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").is_none());
+	}
+
+	#[test]
+	pub fn test_tune_reduces_error() {
+		// not known endgames, whose scores don't depend on the parameters
+		let positions = [
+			"4k3/8/8/8/8/8/3PP3/4K3 w - - 0 1 0-1",
+			"4k3/pp6/8/8/8/8/8/3QK3 w - - 0 1 1-0",
+			"3qk3/8/8/8/8/8/PP6/4K3 w - - 0 1 0-1",
+		].iter().map(|l| TunePosition::parse(l).unwrap()).collect();
+		let mut tuner = Tuner::new(positions, 2);
+		let before = tuner.error(&PARAMS_DEFAULT);
+		let params = tuner.tune(&PARAMS_DEFAULT, 1, |_, _, _| ());
+		debug_assert!(tuner.error(&params) < before);
+	}
+}
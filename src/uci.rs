@@ -1,10 +1,32 @@
 use std::io::{self, BufRead};
-use vampirc_uci::{UciMessage,UciSquare,UciPiece,UciMove,UciInfoAttribute,parse_one};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread::{self, JoinHandle};
+use std::time;
+use vampirc_uci::{UciMessage,UciSquare,UciPiece,UciMove,UciInfoAttribute,UciOptionConfig,UciTimeControl,UciSearchControl,Duration,parse_one};
 use crate::board::{Board,Pos,Piece,Move,Color};
-use crate::minimax::{minimax};
-use crate::negamax::{negamax};
-use crate::negascout::negascout;
+use crate::book::Book;
+use crate::evaluation::new_evaluator;
+use crate::syzygy::Tablebases;
+use crate::search::{SearchControl,SearchLimits,Searcher,new_searcher};
 use crate::misc::*;
+use crate::nnue::Network;
+use crate::params::EvalParams;
+
+/* Number of moves we assume are left in the game when the GUI doesn't tell */
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+/* Time kept in reserve for communication with the GUI, in milliseconds */
+const MOVE_OVERHEAD_MS: u64 = 50;
+/* Upper bound for the `Threads` option */
+const MAX_THREADS: usize = 256;
+/* Upper bound for the `Hash` option, in megabytes */
+const MAX_HASH_MB: usize = 65536;
+/* Upper bound for the `MultiPV` option */
+const MAX_MULTI_PV: usize = 256;
+/* Depth limit of the searches that only end on `stop` or on the clock */
+const MAX_SEARCH_DEPTH: u8 = 64;
+/* Value of the `EvalFile` option that selects the built-in evaluation parameters */
+const BUILTIN_EVAL_FILE: &str = "<builtin>";
 
 impl Pos {
 	pub fn from_uci(us: UciSquare) -> Pos {
@@ -54,24 +76,157 @@ impl Move {
 impl SearchInfo {
 	pub fn to_uci(&self) -> UciMessage {
 		let mut attributes: Vec<UciInfoAttribute> = Vec::new();
-		attributes.push(UciInfoAttribute::from_centipawns(self.score_cp as i32));
+		if let Some(mate) = self.score_mate {
+			attributes.push(UciInfoAttribute::from_mate(mate));
+		} else {
+			attributes.push(UciInfoAttribute::from_centipawns(self.score_cp as i32));
+		}
 		attributes.push(UciInfoAttribute::Depth(self.depth));
 		if let Some(mv) = self.curr_move {
 			attributes.push(UciInfoAttribute::CurrMove(mv.to_uci()));
 		}
 		attributes.push(UciInfoAttribute::Nodes(self.nodes));
 		// attributes.push(UciInfoAttribute::Nps(self.nps));
+		if self.researches > 0 {
+			// must come last, it takes the rest of the line
+			attributes.push(UciInfoAttribute::String(format!("aspiration re-searches {}", self.researches)));
+		}
+		UciMessage::Info(attributes)
+	}
+}
+
+impl SearchResult {
+	/// Reports the result as an `info` message, scored from the point of view of `player`
+	pub fn to_uci(&self, player: Color) -> UciMessage {
+		let mut si = SearchInfo::new();
+		si.depth = self.depth;
+		si.nodes = self.nodes;
+		si.researches = self.researches;
+		si.set_score(if player == Color::White { self.score } else { -self.score });
+		let mut attributes = match si.to_uci() {
+			UciMessage::Info(attributes) => attributes,
+			_ => Vec::new()
+		};
+		let at = attributes.iter().position(|a| matches!(a, UciInfoAttribute::String(_))).unwrap_or(attributes.len());
+		attributes.splice(at..at, vec![
+			UciInfoAttribute::Time(Duration::milliseconds(self.time_ms as i64)),
+			UciInfoAttribute::Pv(self.pv.iter().map(|mv| mv.to_uci()).collect()),
+		]);
 		UciMessage::Info(attributes)
 	}
+
+	/// Reports every MultiPV line as an `info` message, best first
+	pub fn lines_to_uci(&self, player: Color) -> Vec<UciMessage> {
+		if self.lines.len() <= 1 {
+			return vec![self.to_uci(player)];
+		}
+		self.lines.iter().enumerate().map(|(k, line)| {
+			let res = SearchResult {
+				score: line.score,
+				best_move: line.pv[0],
+				pv: line.pv.clone(),
+				..self.clone()
+			};
+			match res.to_uci(player) {
+				UciMessage::Info(mut attributes) => {
+					attributes.insert(0, UciInfoAttribute::MultiPv((k + 1) as u16));
+					UciMessage::Info(attributes)
+				},
+				msg => msg
+			}
+		}).collect()
+	}
+}
+
+impl SearchLimits {
+	/// Derives the search limits from the parameters of a `go` command
+	pub fn from_uci(opts: &Options, player: Color, time_control: Option<UciTimeControl>, search_control: Option<UciSearchControl>) -> SearchLimits {
+		fn millis(d: Option<Duration>) -> u64 {
+			d.map_or(0, |d| d.num_milliseconds().max(0) as u64)
+		}
+
+		let mut limits = SearchLimits::new(opts);
+		match time_control {
+			Some(UciTimeControl::MoveTime(t)) => {
+				limits.movetime_ms = Some(millis(Some(t)));
+			},
+			Some(UciTimeControl::TimeLeft { white_time, black_time, white_increment, black_increment, moves_to_go }) => {
+				let (time, inc) = match player {
+					Color::White => (millis(white_time), millis(white_increment)),
+					Color::Black => (millis(black_time), millis(black_increment)),
+				};
+				let mtg = moves_to_go.map_or(DEFAULT_MOVES_TO_GO, |m| (m as u64).max(1));
+				let budget = time / mtg + inc / 2;
+				limits.movetime_ms = Some(budget.min(time.saturating_sub(MOVE_OVERHEAD_MS)).max(1));
+			},
+			Some(UciTimeControl::Ponder) | Some(UciTimeControl::Infinite) | None => (),
+		}
+		if limits.movetime_ms.is_some() {
+			// the clock ends the search, not the depth of the options
+			limits.max_depth = MAX_SEARCH_DEPTH;
+		}
+		if let Some(sc) = search_control {
+			if let Some(depth) = sc.depth {
+				limits.max_depth = depth;
+			}
+			limits.nodes = sc.nodes;
+		}
+		limits
+	}
+}
+
+/// A search running in the background, so that the UCI loop can keep reading commands
+struct SearchThread {
+	control: Arc<SearchControl>,
+	handle: JoinHandle<Box<dyn Searcher + Send>>,
+}
+impl SearchThread {
+	/// Stops the search, which then reports its best move. Gives the searcher back
+	fn stop(self) -> Box<dyn Searcher + Send> {
+		self.control.abort.store(true, Ordering::Relaxed);
+		self.handle.join().expect("search thread panicked")
+	}
+}
+
+/// The move we expect the opponent to reply with: the second move of the PV,
+/// or the best move stored in the transposition table when the PV is too short
+fn ponder_move(searcher: &mut Box<dyn Searcher + Send>, b: &Board, res: &SearchResult) -> Option<Move> {
+	if let Some(mv) = res.pv.get(1) {
+		return Some(*mv);
+	}
+	let child = b.clone_apply_move(&res.best_move);
+	let mv = searcher.state_mut().tt.probe(child.hash())?.mv?;
+	child.generate_all().into_iter().map(|(m, _)| m).find(|m| *m == mv)
 }
 
 pub fn uci_manager(opts: &Options) {
 	let reader = io::BufReader::new(io::stdin());
 	let mut lines = reader.lines();
 	let mut b: Board = Board::new(Color::White);
+	let mut opts = opts.clone();
+	let mut searcher = Some(new_searcher(&opts));
+	let mut running: Option<SearchThread> = None;
+	// the last clock received, needed to budget a ponder search after `ponderhit`
+	let mut last_clock: Option<UciTimeControl> = None;
 	while let Some(Ok(line)) = lines.next() {
 		// in_mgr_tx.send(line).await.unwrap();
+		if line.trim() == "eval" {
+			// debug command, not part of UCI: evaluation breakdown of the current position
+			println!("{}", new_evaluator(&opts).trace(&b));
+			continue;
+		}
 		let msg: UciMessage = parse_one(&line);
+
+		// Commands other than these are not expected during a search, stop it first
+		match msg {
+			UciMessage::IsReady | UciMessage::PonderHit | UciMessage::Debug(_) => (),
+			_ => {
+				if let Some(t) = running.take() {
+					searcher = Some(t.stop());
+				}
+			}
+		}
+
 		match msg {
 			UciMessage::Uci => {
 				// Initialize the UCI mode of the chess engine.
@@ -79,6 +234,57 @@ pub fn uci_manager(opts: &Options) {
 					name: Some(String::from("RustChess")),
 					author: Some(String::from("Roberto Cornacchia")),
 				});
+				println!("{}", UciMessage::Option(UciOptionConfig::Spin {
+					name: String::from("Threads"),
+					default: Some(opts.threads as i64),
+					min: Some(1),
+					max: Some(MAX_THREADS as i64),
+				}));
+				println!("{}", UciMessage::Option(UciOptionConfig::Spin {
+					name: String::from("Hash"),
+					default: Some(opts.hash_mb as i64),
+					min: Some(1),
+					max: Some(MAX_HASH_MB as i64),
+				}));
+				println!("{}", UciMessage::Option(UciOptionConfig::Spin {
+					name: String::from("MultiPV"),
+					default: Some(opts.multi_pv as i64),
+					min: Some(1),
+					max: Some(MAX_MULTI_PV as i64),
+				}));
+				println!("{}", UciMessage::Option(UciOptionConfig::Combo {
+					name: String::from("Eval"),
+					default: Some(format!("{:?}", opts.eval).to_lowercase()),
+					var: vec![String::from("classic"), String::from("simplified"), String::from("nnue")],
+				}));
+				println!("{}", UciMessage::Option(UciOptionConfig::String {
+					name: String::from("EvalFile"),
+					default: Some(String::from(BUILTIN_EVAL_FILE)),
+				}));
+				println!("{}", UciMessage::Option(UciOptionConfig::String {
+					name: String::from("NnueFile"),
+					default: Some(String::new()),
+				}));
+				println!("{}", UciMessage::Option(UciOptionConfig::Check {
+					name: String::from("Ponder"),
+					default: Some(opts.ponder),
+				}));
+				println!("{}", UciMessage::Option(UciOptionConfig::Check {
+					name: String::from("OwnBook"),
+					default: Some(opts.own_book),
+				}));
+				println!("{}", UciMessage::Option(UciOptionConfig::String {
+					name: String::from("BookFile"),
+					default: Some(String::new()),
+				}));
+				println!("{}", UciMessage::Option(UciOptionConfig::Check {
+					name: String::from("BookRandom"),
+					default: Some(opts.book_random),
+				}));
+				println!("{}", UciMessage::Option(UciOptionConfig::String {
+					name: String::from("SyzygyPath"),
+					default: Some(String::new()),
+				}));
 				println!("{}", UciMessage::UciOk);
 			},
 			UciMessage::IsReady => {
@@ -91,30 +297,236 @@ pub fn uci_manager(opts: &Options) {
 					b = b.clone_apply_move(&lastmove);
 				}
 			},
-			UciMessage::SetOption { name:_, value:_ } => {
-				// TODO
+			UciMessage::SetOption { name, value } => {
+				match (name.to_lowercase().as_str(), value) {
+					("threads", Some(v)) => {
+						if let Ok(n) = v.trim().parse::<usize>() {
+							opts.threads = n.clamp(1, MAX_THREADS);
+							searcher = Some(new_searcher(&opts));
+						}
+					},
+					("hash", Some(v)) => {
+						if let Ok(n) = v.trim().parse::<usize>() {
+							opts.hash_mb = n.clamp(1, MAX_HASH_MB);
+							searcher = Some(new_searcher(&opts));
+						}
+					},
+					("multipv", Some(v)) => {
+						if let Ok(n) = v.trim().parse::<usize>() {
+							opts.multi_pv = n.clamp(1, MAX_MULTI_PV);
+							if let Some(s) = searcher.as_mut() {
+								s.state_mut().opts.multi_pv = opts.multi_pv;
+							}
+						}
+					},
+					("eval", Some(v)) => {
+						if let Ok(e) = v.trim().parse::<Evaluation>() {
+							if e == Evaluation::Nnue && opts.nnue.is_none() {
+								eprintln!("No network loaded, set NnueFile first");
+								continue;
+							}
+							opts.eval = e;
+							searcher = Some(new_searcher(&opts));
+						}
+					},
+					("nnuefile", v) => {
+						let path = v.as_deref().unwrap_or("").trim();
+						if path.is_empty() {
+							opts.nnue = None;
+							if opts.eval == Evaluation::Nnue {
+								opts.eval = Evaluation::Classic;
+							}
+						} else {
+							match Network::load(path) {
+								Ok(net) => opts.nnue = Some(Arc::new(net)),
+								Err(e) => {
+									eprintln!("{}", e);
+									continue;
+								}
+							}
+						}
+						searcher = Some(new_searcher(&opts));
+					},
+					("evalfile", Some(v)) => {
+						let path = v.trim();
+						if path.is_empty() || path == BUILTIN_EVAL_FILE {
+							opts.params = None;
+						} else {
+							match EvalParams::load(path) {
+								Ok(params) => opts.params = Some(Arc::new(params)),
+								Err(e) => {
+									eprintln!("{}", e);
+									continue;
+								}
+							}
+						}
+						searcher = Some(new_searcher(&opts));
+					},
+					("ponder", Some(v)) => {
+						opts.ponder = v.trim().eq_ignore_ascii_case("true");
+					},
+					("ownbook", Some(v)) => {
+						opts.own_book = v.trim().eq_ignore_ascii_case("true");
+						if let Some(s) = searcher.as_mut() {
+							s.state_mut().opts.own_book = opts.own_book;
+						}
+					},
+					("bookrandom", Some(v)) => {
+						opts.book_random = v.trim().eq_ignore_ascii_case("true");
+						if let Some(s) = searcher.as_mut() {
+							s.state_mut().opts.book_random = opts.book_random;
+						}
+					},
+					("bookfile", v) => {
+						let path = v.as_deref().unwrap_or("").trim();
+						if path.is_empty() {
+							opts.book = None;
+						} else {
+							match Book::load(path) {
+								Ok(book) => opts.book = Some(Arc::new(book)),
+								Err(e) => {
+									eprintln!("{}", e);
+									continue;
+								}
+							}
+						}
+						searcher = Some(new_searcher(&opts));
+					},
+					("syzygypath", v) => {
+						let path = v.as_deref().unwrap_or("").trim();
+						if path.is_empty() || path == "<empty>" {
+							opts.syzygy = None;
+						} else {
+							match Tablebases::open(path) {
+								Ok(tb) => opts.syzygy = Some(Arc::new(tb)),
+								Err(e) => {
+									eprintln!("{}", e);
+									continue;
+								}
+							}
+						}
+						searcher = Some(new_searcher(&opts));
+					},
+					_ => eprintln!("Unsupported option: {}", name)
+				}
 			},
 			UciMessage::UciNewGame => {
 				b = Board::new(Color::White);
+				last_clock = None;
+				if let Some(s) = searcher.as_mut() {
+					s.state_mut().tt.clear();
+				}
 			},
-			UciMessage::Go { time_control:_, search_control:_} => {
-				let opts = opts.clone();
+			UciMessage::Go { time_control, search_control } => {
+				let mut s = searcher.take().expect("no search running");
 				let b1 = b.clone();
-				if let Some(res) = match opts.search_algo {
-					SearchAlgorithm::Minimax => minimax(&b1, &opts),
-					SearchAlgorithm::Negamax => negamax(&b1, &opts),
-					SearchAlgorithm::Negascout => negascout(&b1, &opts)
-				} {
-					let _score = res.0;
-					let mv = res.1;
-					let bestmove = UciMessage::best_move(mv.to_uci());
-					b = b.clone_apply_move(&mv);
-					println!("{}", bestmove);
-				} else {
-					// TODO: communicate end of game. How?
+				let pondering = time_control == Some(UciTimeControl::Ponder);
+				let infinite = time_control == Some(UciTimeControl::Infinite);
+				if let Some(UciTimeControl::TimeLeft { .. }) = time_control {
+					last_clock = time_control.clone();
+				}
+				let fixed_depth = search_control.as_ref().is_some_and(|sc| sc.depth.is_some());
+				let clock = if pondering { last_clock.clone() } else { time_control };
+				let mut limits = SearchLimits::from_uci(&opts, b1.player, clock, search_control);
+				if (pondering || infinite) && !fixed_depth {
+					// only `stop` or the clock after `ponderhit` end these
+					limits.max_depth = MAX_SEARCH_DEPTH;
+				}
+
+				let control = s.state_mut().control.clone();
+				control.abort.store(false, Ordering::Relaxed);
+				control.pondering.store(pondering, Ordering::Relaxed);
+				s.state_mut().limits = limits;
+				let ponder = opts.ponder;
+				let handle = {
+					let control = control.clone();
+					thread::spawn(move || {
+						let res = s.search(&b1);
+						// the best move can't be sent before `ponderhit` or `stop` when pondering,
+						// nor before `stop` in infinite mode
+						while (infinite || control.pondering.load(Ordering::Relaxed)) && !control.abort.load(Ordering::Relaxed) {
+							thread::sleep(time::Duration::from_millis(1));
+						}
+						if let Some(res) = res {
+							for info in res.lines_to_uci(b1.player) {
+								println!("{}", info);
+							}
+							let bestmove = match ponder_move(&mut s, &b1, &res) {
+								Some(pmv) if ponder => UciMessage::best_move_with_ponder(res.best_move.to_uci(), pmv.to_uci()),
+								_ => UciMessage::best_move(res.best_move.to_uci()),
+							};
+							println!("{}", bestmove);
+						} else {
+							// TODO: communicate end of game. How?
+						}
+						s
+					})
+				};
+				running = Some(SearchThread { control, handle });
+			},
+			UciMessage::PonderHit => {
+				if let Some(t) = running.as_ref() {
+					t.control.pondering.store(false, Ordering::Relaxed);
 				}
 			},
+			UciMessage::Stop => (),
+			UciMessage::Quit => break,
 			_ => eprintln!("Don't know what to do")
 		}
 	};
+	if let Some(t) = running.take() {
+		t.stop();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use vampirc_uci::{UciSearchControl,UciTimeControl,Duration};
+
+	use crate::board::{Board,Color};
+	use crate::misc::{OPTS_DEFAULT,SearchAlgorithm};
+	use crate::search::{new_searcher,SearchLimits};
+	use crate::uci::{ponder_move,MAX_SEARCH_DEPTH};
+
+	#[test]
+	pub fn test_limits_depth() {
+		let opts = OPTS_DEFAULT.clone();
+		let clock = UciTimeControl::TimeLeft {
+			white_time: Some(Duration::milliseconds(60000)),
+			black_time: Some(Duration::milliseconds(60000)),
+			white_increment: None,
+			black_increment: None,
+			moves_to_go: None,
+		};
+		// a clock or a move time lifts the depth of the options
+		let limits = SearchLimits::from_uci(&opts, Color::White, Some(clock.clone()), None);
+		debug_assert_eq!(limits.max_depth, MAX_SEARCH_DEPTH);
+		let limits = SearchLimits::from_uci(&opts, Color::Black, Some(UciTimeControl::MoveTime(Duration::milliseconds(100))), None);
+		debug_assert_eq!(limits.max_depth, MAX_SEARCH_DEPTH);
+		// unless a depth is given
+		let depth = UciSearchControl { search_moves: vec![], mate: None, depth: Some(7), nodes: None };
+		let limits = SearchLimits::from_uci(&opts, Color::White, Some(clock), Some(depth));
+		debug_assert_eq!(limits.max_depth, 7);
+		// without a clock, the options decide
+		let limits = SearchLimits::from_uci(&opts, Color::White, None, None);
+		debug_assert_eq!(limits.max_depth, opts.max_depth);
+	}
+
+	#[test]
+	pub fn test_ponder_move() {
+		let b = Board::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+		let mut opts = OPTS_DEFAULT.clone();
+		opts.search_algo = SearchAlgorithm::Negascout;
+		opts.max_depth = 3;
+		let mut searcher = new_searcher(&opts);
+		let mut res = searcher.search(&b).expect("a move");
+		debug_assert_eq!(ponder_move(&mut searcher, &b, &res), Some(res.pv[1]));
+		// a one move PV: the reply comes from the transposition table
+		res.pv.truncate(1);
+		let reply = ponder_move(&mut searcher, &b, &res).expect("a reply");
+		let child = b.clone_apply_move(&res.best_move);
+		debug_assert!(child.generate_all().iter().any(|(mv, _)| *mv == reply));
+		// nothing to go by
+		debug_assert_eq!(ponder_move(&mut new_searcher(&opts), &b, &res), None);
+	}
 }
@@ -0,0 +1,124 @@
+use crate::board::{Board, Color, Piece, Pos, Tile};
+
+/* 
+ * Zobrist hashing: https://www.chessprogramming.org/Zobrist_Hashing
+ * Keys are generated at compile time with splitmix64 from a fixed seed,
+ * so hashes are the same across runs and platforms.
+*/
+
+const PIECE_KEYS: usize = 2 * 6 * 64;
+const CASTLE_KEYS: usize = 4;
+const NUM_KEYS: usize = PIECE_KEYS + CASTLE_KEYS + 1;
+const SEED: u64 = 0x4475_6368_6573_7321;
+
+const fn generate_keys() -> [u64; NUM_KEYS] {
+	let mut keys = [0u64; NUM_KEYS];
+	let mut state = SEED;
+	let mut i = 0;
+	while i < NUM_KEYS {
+		state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		keys[i] = z ^ (z >> 31);
+		i += 1;
+	}
+	keys
+}
+
+static KEYS: [u64; NUM_KEYS] = generate_keys();
+
+#[inline]
+fn piece_key(color: Color, piece: usize, pos: Pos) -> u64 {
+	KEYS[((color as usize * 6 + piece) * 64) + (pos.row * 8 + pos.col) as usize]
+}
+
+impl Board {
+	/// Zobrist hash of the position: pieces, side to move and castling rights
+	pub fn hash(&self) -> u64 {
+		if let Some(h) = self.stored_hash.get() {
+			return h;
+		}
+		let mut h = 0;
+		for c in 0..8 {
+			for r in 0..8 {
+				let pos = Pos::at(c, r).unwrap();
+				if let Some(tile) = self.at(pos) {
+					h ^= piece_key(tile.color, tile.piece as usize, pos);
+				}
+			}
+		}
+		for color in [Color::White, Color::Black] {
+			if self.can_castle_ks[color as usize] {
+				h ^= KEYS[PIECE_KEYS + 2 * color as usize];
+			}
+			if self.can_castle_qs[color as usize] {
+				h ^= KEYS[PIECE_KEYS + 2 * color as usize + 1];
+			}
+		}
+		if self.player == Color::White {
+			h ^= KEYS[PIECE_KEYS + CASTLE_KEYS];
+		}
+		self.stored_hash.set(Some(h));
+		h
+	}
+
+	/// Zobrist hash of the pawns alone, used as key of the pawn hash table.
+	/// Kept up to date by `make_move`
+	#[inline]
+	pub fn pawn_hash(&self) -> u64 {
+		self.pawn_key
+	}
+
+	/// Computes the pawn hash from scratch
+	pub fn scan_pawn_hash(&self) -> u64 {
+		let mut h = 0;
+		for c in 0..8 {
+			for r in 0..8 {
+				let pos = Pos::at(c, r).unwrap();
+				if let Some(tile) = self.at(pos) {
+					h ^= pawn_key(*tile, pos);
+				}
+			}
+		}
+		h
+	}
+}
+
+/// Key of a tile in the pawn hash: zero for pieces other than pawns
+#[inline]
+pub fn pawn_key(tile: Tile, pos: Pos) -> u64 {
+	if tile.piece == Piece::Pawn {
+		piece_key(tile.color, Piece::Pawn as usize, pos)
+	} else {
+		0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::board::{Board,Color,Move};
+
+	#[test]
+	pub fn test_hash_transposition() {
+		let b = Board::new(Color::White);
+		let mvs1 = ["g1f3", "g8f6", "b1c3"];
+		let mvs2 = ["b1c3", "g8f6", "g1f3"];
+		let b1 = mvs1.iter().fold(b.clone(), |b, mv| b.clone_apply_move(&mv.parse::<Move>().unwrap()));
+		let b2 = mvs2.iter().fold(b.clone(), |b, mv| b.clone_apply_move(&mv.parse::<Move>().unwrap()));
+		debug_assert_eq!(b1.hash(), b2.hash());
+		debug_assert_ne!(b.hash(), b.clone_null_move().hash());
+	}
+
+	#[test]
+	pub fn test_pawn_hash() {
+		// pawn moves, a pawn capture and a promotion
+		let b = Board::from_fen("4k3/1P6/8/3p4/4P3/8/8/4K3 w - - 0 1").unwrap();
+		let b = ["e4d5", "e8e7", "b7b8"].iter().fold(b, |b, mv| {
+			let b = b.clone_apply_move(&mv.parse::<Move>().unwrap());
+			debug_assert_eq!(b.pawn_hash(), b.scan_pawn_hash());
+			b
+		});
+		debug_assert_eq!(b.pawn_hash(), Board::from_fen("1Q6/4k3/8/3P4/8/8/8/4K3 w - - 0 1").unwrap().pawn_hash());
+	}
+}
@@ -0,0 +1,340 @@
+#!/usr/bin/env python3
+"""
+Writes the KQvK and KRvK Syzygy tables used by the tests of src/syzygy.rs.
+
+The values come from a retrograde analysis of the endgames, and the files follow the
+format of the reference generator by Ronald de Man: same header, index of the pieces,
+RePair compression and canonical Huffman code. The compression is simpler (a few hundred
+pairs, small blocks), which makes the files larger but no different to read.
+
+    python3 testdata/syzygy/generate.py [directory]
+"""
+
+import heapq
+import os
+import struct
+import sys
+from collections import Counter
+
+WDL_MAGIC = bytes([0x71, 0xE8, 0x23, 0x5D])
+DTZ_MAGIC = bytes([0xD7, 0x66, 0x0C, 0xA5])
+
+# table codes of the pieces: 1..6 for white pawn..king, 9..14 for black
+W_ROOK, W_QUEEN, W_KING, B_KING = 4, 5, 6, 14
+
+BLOCK_SIZE_LOG2 = 8
+SPAN_LOG2 = 10
+MAX_PAIRS = 300
+
+# Squares are numbered from a1 = 0 to h8 = 63
+def rank_of(sq):
+    return sq >> 3
+
+def file_of(sq):
+    return sq & 7
+
+def off_a1h8(sq):
+    return rank_of(sq) - file_of(sq)
+
+MAP_B1H1H7 = [0] * 64
+MAP_A1D1D4 = [0] * 64
+
+def init_maps():
+    code = 0
+    for sq in range(64):
+        if off_a1h8(sq) < 0:
+            MAP_B1H1H7[sq] = code
+            code += 1
+    diagonal = []
+    code = 0
+    for sq in range(28):
+        if off_a1h8(sq) < 0 and file_of(sq) <= 3:
+            MAP_A1D1D4[sq] = code
+            code += 1
+        elif off_a1h8(sq) == 0 and file_of(sq) <= 3:
+            diagonal.append(sq)
+    for sq in diagonal:
+        MAP_A1D1D4[sq] = code
+        code += 1
+
+init_maps()
+
+def encode(squares):
+    """Index of three unique pieces, in table order, as in the reference probing code"""
+    sq = list(squares)
+    if file_of(sq[0]) > 3:
+        sq = [s ^ 7 for s in sq]
+    if rank_of(sq[0]) > 3:
+        sq = [s ^ 56 for s in sq]
+    for i in range(3):
+        if off_a1h8(sq[i]) == 0:
+            continue
+        if off_a1h8(sq[i]) > 0:
+            sq = sq[:i] + [((s >> 3) | (s << 3)) & 63 for s in sq[i:]]
+        break
+    s0, s1, s2 = sq
+    adjust1 = int(s1 > s0)
+    adjust2 = int(s2 > s0) + int(s2 > s1)
+    if off_a1h8(s0) != 0:
+        return (MAP_A1D1D4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+    if off_a1h8(s1) != 0:
+        return (6 * 63 + rank_of(s0) * 28 + MAP_B1H1H7[s1]) * 62 + s2 - adjust2
+    if off_a1h8(s2) != 0:
+        return 6 * 63 * 62 + 4 * 28 * 62 + rank_of(s0) * 7 * 28 + (rank_of(s1) - adjust1) * 28 + MAP_B1H1H7[s2]
+    return 6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank_of(s0) * 7 * 6 + (rank_of(s1) - adjust1) * 6 + (rank_of(s2) - adjust2)
+
+TB_SIZE = 31332
+
+def adjacent(a, b):
+    return a != b and abs(rank_of(a) - rank_of(b)) <= 1 and abs(file_of(a) - file_of(b)) <= 1
+
+def king_moves(sq):
+    return [t for t in range(64) if adjacent(sq, t)]
+
+QUEEN_DIRS = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)]
+ROOK_DIRS = QUEEN_DIRS[:4]
+
+def slides(sq, dirs, occupied):
+    """Squares reached from `sq`, stopping on the first occupied one"""
+    out = []
+    for df, dr in dirs:
+        f, r = file_of(sq) + df, rank_of(sq) + dr
+        while 0 <= f < 8 and 0 <= r < 8:
+            t = r * 8 + f
+            out.append(t)
+            if t in occupied:
+                break
+            f, r = f + df, r + dr
+    return out
+
+def solve(dirs):
+    """Plies to mate of the positions of the endgame by index, with white and with black to move:
+    None for draws, and no entry for illegal positions"""
+    positions = {}
+    for wk in range(64):
+        for x in range(64):
+            for bk in range(64):
+                if len({wk, x, bk}) == 3 and not adjacent(wk, bk):
+                    idx = encode((wk, x, bk))
+                    positions.setdefault(idx, (wk, x, bk))
+    assert max(positions) < TB_SIZE
+
+    def attacked_by_white(sq, wk, x):
+        return adjacent(wk, sq) or sq in slides(x, dirs, {wk, x})
+
+    # children of each position, as indices, or None for a capture of the piece
+    white_children, black_children = {}, {}
+    white_legal, mates, stalemates = set(), set(), set()
+    for idx, (wk, x, bk) in positions.items():
+        if bk not in slides(x, dirs, {wk, bk}):
+            white_legal.add(idx)
+            children = [encode((t, x, bk)) for t in king_moves(wk) if t != x and not adjacent(t, bk)]
+            children += [encode((wk, t, bk)) for t in slides(x, dirs, {wk, bk}) if t not in (wk, bk)]
+            white_children[idx] = children
+        children = []
+        for t in king_moves(bk):
+            if t == x:
+                if not adjacent(wk, x):
+                    children.append(None)
+            elif not adjacent(wk, t) and t not in slides(x, dirs, {wk, x}):
+                children.append(encode((wk, x, t)))
+        if not children:
+            (mates if attacked_by_white(bk, wk, x) else stalemates).add(idx)
+        black_children[idx] = children
+
+    white, black = {}, {}
+    for idx in mates:
+        black[idx] = 0
+    ply = 0
+    while True:
+        ply += 1
+        found = False
+        if ply % 2 == 1:
+            for idx in white_legal:
+                if idx not in white and any(black.get(c) == ply - 1 for c in white_children[idx]):
+                    white[idx] = ply
+                    found = True
+        else:
+            for idx, children in black_children.items():
+                if idx in black or idx in stalemates or None in children:
+                    continue
+                if all(c in white for c in children):
+                    black[idx] = ply
+                    found = True
+        if not found and ply % 2 == 0:
+            break
+
+    wtm = {idx: white.get(idx) for idx in white_legal}
+    btm = {idx: black.get(idx) for idx in positions}
+    return wtm, btm
+
+def fill(values):
+    """Don't care values repeat the previous one, which compresses best"""
+    last = next(v for v in values if v is not None)
+    out = []
+    for v in values:
+        if v is not None:
+            last = v
+        out.append(last)
+    return out
+
+def repair(values):
+    """Symbols standing for runs of values: leaves first, then pairs of symbols"""
+    leaves = sorted(set(values))
+    symbols = [(v, None) for v in leaves]
+    lengths = [1] * len(leaves)
+    code = {v: i for i, v in enumerate(leaves)}
+    seq = [code[v] for v in values]
+    while len(symbols) < len(leaves) + MAX_PAIRS:
+        counts = Counter(zip(seq, seq[1:]))
+        (a, b), n = counts.most_common(1)[0]
+        if n < 8 or lengths[a] + lengths[b] > 256:
+            break
+        new = len(symbols)
+        symbols.append((a, b))
+        lengths.append(lengths[a] + lengths[b])
+        out = []
+        i = 0
+        while i < len(seq):
+            if i + 1 < len(seq) and seq[i] == a and seq[i + 1] == b:
+                out.append(new)
+                i += 2
+            else:
+                out.append(seq[i])
+                i += 1
+        seq = out
+    return symbols, lengths, seq
+
+def huffman(seq, count):
+    """Code lengths of the symbols"""
+    freq = Counter(seq)
+    heap = [(freq[s], s, [s]) for s in range(count) if freq[s] > 0]
+    heapq.heapify(heap)
+    depth = [0] * count
+    if len(heap) == 1:
+        depth[heap[0][1]] = 1
+    while len(heap) > 1:
+        f1, k1, s1 = heapq.heappop(heap)
+        f2, k2, s2 = heapq.heappop(heap)
+        for s in s1 + s2:
+            depth[s] += 1
+        heapq.heappush(heap, (f1 + f2, min(k1, k2), s1 + s2))
+    return depth
+
+class BitWriter:
+    def __init__(self):
+        self.bits = []
+
+    def write(self, value, length):
+        self.bits.extend((value >> (length - 1 - i)) & 1 for i in range(length))
+
+    def to_bytes(self, size):
+        bits = self.bits + [0] * (size * 8 - len(self.bits))
+        return bytes(int("".join(map(str, bits[i:i + 8])), 2) for i in range(0, len(bits), 8))
+
+def compress(values):
+    """The sizes data of a sub-table and its sparse index, block lengths and blocks"""
+    if len(set(values)) == 1:
+        return struct.pack("<BB", 0x80, values[0]), b"", b"", b""
+
+    symbols, lengths, seq = repair(values)
+    depth = huffman(seq, len(symbols))
+    coded = [s for s in range(len(symbols)) if depth[s] > 0]
+    max_len = max(depth)
+    min_len = min(depth[s] for s in coded)
+    assert max_len <= 32
+
+    # longer codes come first, and have lower values; symbols only found in pairs come last
+    order = sorted(coded, key=lambda s: (-depth[s], s))
+    order += [s for s in range(len(symbols)) if depth[s] == 0]
+    number = {s: i for i, s in enumerate(order)}
+    count = Counter(depth[s] for s in coded)
+    lowest = {max_len: 0}
+    base = {max_len: 0}
+    for n in range(max_len - 1, min_len - 1, -1):
+        lowest[n] = lowest[n + 1] + count[n + 1]
+        base[n] = (base[n + 1] + count[n + 1]) // 2
+    codes = {s: (base[depth[s]] + number[s] - lowest[depth[s]], depth[s]) for s in coded}
+
+    btree = bytearray()
+    for s in order:
+        left, right = symbols[s]
+        if right is None:
+            left, right = left, 0xFFF
+        else:
+            left, right = number[left], number[right]
+        btree += bytes([left & 0xFF, (left >> 8) | ((right & 0xF) << 4), right >> 4])
+    if len(symbols) & 1:
+        btree += b"\0"
+
+    # whole symbols per block, keeping 64 bits at the end for the reads ahead of the decoder
+    block_size = 1 << BLOCK_SIZE_LOG2
+    blocks, block_values, starts = [], [], []
+    writer, values_in_block, start = BitWriter(), 0, 0
+    for s in seq:
+        code, n = codes[s]
+        if len(writer.bits) + n > block_size * 8 - 64 or values_in_block + lengths[s] > 65536:
+            blocks.append(writer.to_bytes(block_size))
+            block_values.append(values_in_block)
+            starts.append(start)
+            start += values_in_block
+            writer, values_in_block = BitWriter(), 0
+        writer.write(code, n)
+        values_in_block += lengths[s]
+    blocks.append(writer.to_bytes(block_size))
+    block_values.append(values_in_block)
+    starts.append(start)
+
+    span = 1 << SPAN_LOG2
+    sparse = bytearray()
+    for k in range((len(values) + span - 1) // span):
+        target = k * span + span // 2
+        block = max(i for i in range(len(starts)) if starts[i] <= target)
+        sparse += struct.pack("<IH", block, target - starts[block])
+
+    lengths_data = b"".join(struct.pack("<H", n - 1) for n in block_values)
+    sizes = struct.pack("<BBBBIBB", 0, BLOCK_SIZE_LOG2, SPAN_LOG2, 0, len(blocks), max_len, min_len)
+    sizes += b"".join(struct.pack("<H", lowest[n]) for n in range(min_len, max_len + 1))
+    sizes += struct.pack("<H", len(symbols)) + btree
+    return sizes, bytes(sparse), lengths_data, b"".join(blocks)
+
+def table(magic, pieces, sides):
+    """A table file of three pieces without pawns, with one list of values per side to move"""
+    out = bytearray(magic)
+    out.append(1)  # not symmetric, no pawns
+    out.append(0)  # order of the groups
+    out += bytes(p | (p << 4) for p in pieces)
+    if len(out) & 1:
+        out.append(0)
+    parts = [compress(values) for values in sides]
+    for sizes, _, _, _ in parts:
+        out += sizes
+    if magic == DTZ_MAGIC and len(out) & 1:
+        out.append(0)
+    for _, sparse, _, _ in parts:
+        out += sparse
+    for _, _, lengths, _ in parts:
+        out += lengths
+    for _, _, _, data in parts:
+        out += bytes(-len(out) % 64)
+        out += data
+    return bytes(out)
+
+def write(directory, name, piece, dirs, longest):
+    wtm, btm = solve(dirs)
+    assert None not in wtm.values() and max(wtm.values()) == longest
+    wdl = [fill([None if i not in wtm else 4 for i in range(TB_SIZE)]),
+           fill([None if i not in btm else 2 if btm[i] is None else 0 for i in range(TB_SIZE)])]
+    # DTZ only for white to move, in moves: mate in n is stored as n - 1
+    dtz = [fill([None if i not in wtm else (wtm[i] - 1) // 2 for i in range(TB_SIZE)])]
+    pieces = [W_KING, piece, B_KING]
+    with open(os.path.join(directory, name + ".rtbw"), "wb") as f:
+        f.write(table(WDL_MAGIC, pieces, wdl))
+    with open(os.path.join(directory, name + ".rtbz"), "wb") as f:
+        f.write(table(DTZ_MAGIC, pieces, dtz))
+
+if __name__ == "__main__":
+    directory = sys.argv[1] if len(sys.argv) > 1 else os.path.dirname(os.path.abspath(__file__))
+    # the longest mates are in 10 and 16 moves
+    write(directory, "KQvK", W_QUEEN, QUEEN_DIRS, 19)
+    write(directory, "KRvK", W_ROOK, ROOK_DIRS, 31)